@@ -1,97 +1,403 @@
-use std::{io::Write, thread, time::Duration};
+use std::{
+    collections::HashMap,
+    io,
+    net::{Shutdown, SocketAddr},
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 use luminmq_core::{
-    msg::{ConsumerType, Message, MessageStatus, MessageType},
-    protocol::Protocol,
+    handshake::{Capabilities, Hello},
+    msg::{ConsumerType, Message, MessageStatus, MessageType, SystemAction},
+    protocol::{Protocol, ProtocolDecoder},
+    session::Session,
+    tool::{codec::decode, net},
     topic::Topic,
     types::ConsumerBinder,
 };
 use mio::{Events, Interest, Poll, Token, net::TcpStream};
-use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
+use tokio::sync::{oneshot, watch};
+use tracing::{Level, event};
+
+use crate::{
+    config::{HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT, LISTENER_PORT},
+    error::ClientError,
+};
 
-use crate::config::LISTENER_PORT;
-use std::io::{self};
+const CLIENT_TOKEN: Token = Token(0);
 
 /// luminmq client module
-pub struct LuminMQClient;
+/// a connection to the server, responses are matched to their requests by correlation id.
+#[derive(Clone)]
+pub struct LuminMQClient {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    stream: TcpStream,
+    // serializes frame writes from different threads
+    write_lock: Mutex<()>,
+    // requests waiting for a response
+    // k: correlation id v: response sender
+    pending: Mutex<HashMap<u64, oneshot::Sender<Protocol>>>,
+    next_correlation_id: AtomicU64,
+    // negotiated with the server by the handshake
+    session: RwLock<Option<Session>>,
+    // set once the connection is closed
+    closed: watch::Sender<bool>,
+    // producer id and next sequence of idempotent sends
+    producer_id: RwLock<String>,
+    next_sequence: AtomicU64,
+}
+
 impl LuminMQClient {
+    // connect to the configured server and consume pushed messages until the connection closes.
     pub async fn start() -> std::io::Result<()> {
         let addr = LISTENER_PORT.lock().unwrap().parse().unwrap();
-        let mut stream = TcpStream::connect(addr)?;
-        let mut poll = Poll::new()?;
+        let client = LuminMQClient::connect(addr)
+            .await
+            .map_err(io::Error::other)?;
+        // subscribe to the channel of every bound consumer function.
+        for (group_id, topic) in ConsumerBinder::keys() {
+            client
+                .subscribe(&group_id, &topic)
+                .await
+                .map_err(io::Error::other)?;
+        }
+        client.closed().await;
+        Ok(())
+    }
+    // connect to a server and complete the handshake.
+    pub async fn connect(addr: SocketAddr) -> Result<Self, ClientError> {
+        let std_stream = std::net::TcpStream::connect(addr)?;
+        std_stream.set_nonblocking(true)?;
+        let mut stream = TcpStream::from_std(std_stream);
+        let poll = Poll::new()?;
+        poll.registry()
+            .register(&mut stream, CLIENT_TOKEN, Interest::READABLE)?;
+        let (closed, _) = watch::channel(false);
+        // unique per connection unless set_producer continues another producer
+        let producer_id = format!(
+            "{}-{}-{}",
+            std::process::id(),
+            local_port(&stream),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0)
+        );
+        let client = LuminMQClient {
+            inner: Arc::new(Inner {
+                stream,
+                write_lock: Mutex::new(()),
+                pending: Mutex::new(HashMap::new()),
+                next_correlation_id: AtomicU64::new(1),
+                session: RwLock::new(None),
+                closed,
+                producer_id: RwLock::new(producer_id),
+                next_sequence: AtomicU64::new(1),
+            }),
+        };
+        let inner = Arc::clone(&client.inner);
+        thread::spawn(move || inner.run(poll));
+        client.handshake().await?;
+        Ok(client)
+    }
+    // the handshake is the first request on every connection.
+    async fn handshake(&self) -> Result<(), ClientError> {
+        let hello = Message::system(
+            SystemAction::ClientHello,
+            MessageStatus::None,
+            Hello::default().to_data(),
+        );
+        let reply = self.response(hello).await?;
+        if reply.action != SystemAction::ServerHello {
+            return Err(ClientError::InvalidResponse(
+                "Expected a server hello.".to_string(),
+            ));
+        }
+        let hello = Hello::from_data(&reply.data)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        *self.inner.session.write().unwrap() = Some(Session::new(&hello));
+        Ok(())
+    }
+    // the session negotiated by the handshake.
+    pub fn session(&self) -> Option<Session> {
+        self.inner.session.read().unwrap().clone()
+    }
+    // send a request, the future resolves with the frame answering it.
+    pub async fn request(&self, mut message: Message) -> Result<Protocol, ClientError> {
+        let (tx, rx) = oneshot::channel();
+        let correlation_id = self
+            .inner
+            .next_correlation_id
+            .fetch_add(1, Ordering::Relaxed);
+        message.correlation_id = correlation_id;
+        self.inner
+            .pending
+            .lock()
+            .unwrap()
+            .insert(correlation_id, tx);
+        if *self.inner.closed.borrow() {
+            self.inner.pending.lock().unwrap().remove(&correlation_id);
+            return Err(ClientError::ConnectionClosed);
+        }
+        if let Err(e) = self.inner.write(&message) {
+            self.inner.pending.lock().unwrap().remove(&correlation_id);
+            return Err(e.into());
+        }
+        rx.await.map_err(|_| ClientError::ConnectionClosed)
+    }
+    // send a request answered by a single message, error frames become ClientError::Server.
+    async fn response(&self, message: Message) -> Result<Message, ClientError> {
+        let protocol = self.request(message).await?;
+        if let Some(e) = protocol.get_error() {
+            return Err(ClientError::Server(e));
+        }
+        protocol
+            .get_message()
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))
+    }
+    // pull a message from a channel in pull mode.
+    pub async fn pull(&self, group_id: &str, topic: &str) -> Result<Message, ClientError> {
+        let message = Message {
+            group_id: group_id.to_string(),
+            topic: Topic::new(topic.to_string()),
+            msg_type: MessageType::Business,
+            consumer_type: ConsumerType::Pull,
+            ..Default::default()
+        };
+        self.response(message).await
+    }
+    // consume the channel of a group and topic, the server pushes its messages to this connection.
+    pub async fn subscribe(&self, group_id: &str, topic: &str) -> Result<(), ClientError> {
+        self.system_request(SystemAction::Subscribe, group_id, topic)
+            .await
+            .map(|_| ())
+    }
+    // stop consuming the channel of a group and topic.
+    pub async fn unsubscribe(&self, group_id: &str, topic: &str) -> Result<(), ClientError> {
+        self.system_request(SystemAction::Unsubscribe, group_id, topic)
+            .await
+            .map(|_| ())
+    }
+    // open a transaction on this connection. messages sent until the commit are staged by the
+    // server and invisible to consumers, send resolves with id 0 for them.
+    // a transaction belongs to the connection, so every send of a clone of the client is staged.
+    pub async fn begin(&self) -> Result<(), ClientError> {
+        self.system_request(SystemAction::Begin, "", "")
+            .await
+            .map(|_| ())
+    }
+    // enqueue every message staged by the transaction, resolves with their ids in send order.
+    // if one of their channels no longer exists none is enqueued.
+    pub async fn commit(&self) -> Result<Vec<u64>, ClientError> {
+        let reply = self.system_request(SystemAction::Commit, "", "").await?;
+        decode(&reply.data).map_err(|e| ClientError::InvalidResponse(e.to_string()))
+    }
+    // discard every message staged by the transaction.
+    pub async fn abort(&self) -> Result<(), ClientError> {
+        self.system_request(SystemAction::Abort, "", "")
+            .await
+            .map(|_| ())
+    }
+    // send a system request for a channel, the server answers with the same action.
+    async fn system_request(
+        &self,
+        action: SystemAction,
+        group_id: &str,
+        topic: &str,
+    ) -> Result<Message, ClientError> {
+        let mut message = Message::system(action, MessageStatus::None, []);
+        message.group_id = group_id.to_string();
+        message.topic = Topic::new(topic.to_string());
+        let reply = self.response(message).await?;
+        if reply.action != action || reply.status != MessageStatus::Success {
+            return Err(ClientError::InvalidResponse(format!(
+                "Expected a successful {:?} answer.",
+                action
+            )));
+        }
+        Ok(reply)
+    }
+    // send a message to its channel, the future resolves once the broker confirmed it.
+    // an ack resolves with the message id assigned by the broker,
+    // a nack resolves with ClientError::Server carrying the reason.
+    pub async fn send(&self, mut message: Message) -> Result<u64, ClientError> {
+        message.msg_type = MessageType::Business;
+        message.consumer_type = ConsumerType::Send;
+        let ack = self.response(message).await?;
+        if ack.action != SystemAction::PublishAck {
+            return Err(ClientError::InvalidResponse(
+                "Expected a publish ack.".to_string(),
+            ));
+        }
+        Ok(ack.id)
+    }
+    // make a message idempotent with the producer id of this client and its next sequence,
+    // resending the message, e.g. after a timeout, is applied once by the broker.
+    pub fn idempotent(&self, message: &mut Message) {
+        let sequence = self.inner.next_sequence.fetch_add(1, Ordering::Relaxed);
+        message.set_producer(self.producer_id(), sequence);
+    }
+    pub fn producer_id(&self) -> String {
+        self.inner.producer_id.read().unwrap().clone()
+    }
+    // continue the sends of a producer, e.g. after reconnecting. the next sequence must be above
+    // every sequence the producer used before.
+    pub fn set_producer(&self, producer_id: impl Into<String>, next_sequence: u64) {
+        *self.inner.producer_id.write().unwrap() = producer_id.into();
+        self.inner
+            .next_sequence
+            .store(next_sequence, Ordering::Relaxed);
+    }
+    // send a message to its channel without waiting for a confirm.
+    pub fn send_unconfirmed(&self, mut message: Message) -> Result<(), ClientError> {
+        message.msg_type = MessageType::Business;
+        message.consumer_type = ConsumerType::Send;
+        message.correlation_id = 0;
+        self.inner.write(&message).map_err(Into::into)
+    }
+    // close the connection, pending requests fail with ConnectionClosed.
+    pub fn close(&self) {
+        let _ = self.inner.stream.shutdown(Shutdown::Both);
+    }
+    // wait until the connection is closed.
+    pub async fn closed(&self) {
+        let mut closed = self.inner.closed.subscribe();
+        let _ = closed.wait_for(|closed| *closed).await;
+    }
+}
+
+impl Inner {
+    // io loop, runs on its own thread until the connection is closed.
+    // the server is pinged when the connection is idle and the connection is closed
+    // when the server sent nothing within the heartbeat timeout.
+    fn run(&self, mut poll: Poll) {
         let mut events = Events::with_capacity(128);
-        poll.registry().register(
-            &mut stream,
-            Token(0),
-            Interest::READABLE | Interest::WRITABLE,
-        )?;
-        loop {
-            poll.poll(&mut events, None)?;
+        let mut decoder = ProtocolDecoder::new();
+        let heartbeat_interval = *HEARTBEAT_INTERVAL.lock().unwrap();
+        let heartbeat_timeout = *HEARTBEAT_TIMEOUT.lock().unwrap();
+        let mut last_seen = Instant::now();
+        let mut last_ping = Instant::now();
+        'poll: loop {
+            if let Err(e) = poll.poll(&mut events, Some(heartbeat_interval)) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                break;
+            }
             for event in &events {
-                match event.token() {
-                    Token(0) => {
-                        if event.is_readable() {
-                            Protocol::handle(&stream, |mss| {
-                                // test
-                                thread::sleep(Duration::from_millis(3000));
-                                if mss.status == MessageStatus::Success {
-                                    match ConsumerBinder::get((
-                                        mss.group_id.to_string(),
-                                        mss.topic.name.to_string(),
-                                    )) {
-                                        Ok(fn_consumer) => match fn_consumer(mss) {
-                                            Ok(_s) => {
-                                                // consumption success
-                                            }
-                                            Err(_e) => {
-                                                // consumption fail
-                                            }
-                                        },
-                                        Err(_e) => (),
-                                    };
-                                } else if mss.status == MessageStatus::Fail {
-                                    // error return code
-                                }
-                            });
-                        }
-
-                        if event.is_writable() {
-                            // test protocol
-                            let protocol = &mut Protocol::default();
-                            let mut msg = Message::default();
-                            msg.group_id = "group-test".to_string();
-                            msg.topic = Topic::new("topic-test".to_string());
-                            msg.consumer_type = ConsumerType::Pull;
-                            msg.msg_type = MessageType::Business;
-                            msg.data = "12312".to_string();
-                            protocol.insert_message(msg.to_messagedto());
-                            let _ = protocol.ready();
-                            let protocol_buf = protocol.to_byte_vec();
-                            match stream.write(&protocol_buf) {
-                                Ok(n) if n < protocol_buf.len() => {
-                                    return Err(io::ErrorKind::WriteZero.into());
-                                }
-                                Ok(_) => {}
-                                Err(ref err) if would_block(err) => {}
-                                Err(ref err) if interrupted(err) => {}
-                                Err(err) => {
-                                    return Err(err);
-                                }
-                            }
-                        }
+                if event.token() == CLIENT_TOKEN && event.is_readable() {
+                    last_seen = Instant::now();
+                    let result = Protocol::handle(&self.stream, &mut decoder, |protocol| {
+                        self.dispatch(protocol);
+                        Ok(())
+                    });
+                    if let Err(e) = result {
+                        event!(Level::WARN, "connection closed: {}", e);
+                        break 'poll;
                     }
-                    _token => {}
-                    _ => (),
                 }
             }
+            if last_seen.elapsed() >= heartbeat_timeout {
+                event!(Level::WARN, "heartbeat timeout, closing connection");
+                let _ = self.stream.shutdown(Shutdown::Both);
+                break;
+            }
+            if last_seen.elapsed() >= heartbeat_interval
+                && last_ping.elapsed() >= heartbeat_interval
+            {
+                last_ping = Instant::now();
+                let ping = Message::system(SystemAction::Ping, MessageStatus::None, []);
+                let _ = self.write(&ping);
+            }
         }
+        self.closed.send_replace(true);
+        // dropping the senders fails every pending request
+        self.pending.lock().unwrap().clear();
+    }
+    // a frame answering a request completes the request,
+    // every other frame carries messages pushed by the server.
+    fn dispatch(&self, protocol: Protocol) {
+        let waiting = match protocol.correlation_id() {
+            0 => None,
+            correlation_id => self.pending.lock().unwrap().remove(&correlation_id),
+        };
+        match waiting {
+            Some(tx) => {
+                let _ = tx.send(protocol);
+            }
+            None => {
+                for message in protocol.get_messages() {
+                    if message.msg_type == MessageType::System {
+                        self.handle_system(message);
+                    } else {
+                        self.consume(message);
+                    }
+                }
+            }
+        }
+    }
+    // system messages sent by the server.
+    fn handle_system(&self, message: Message) {
+        if message.action == SystemAction::Ping {
+            let mut pong = Message::system(SystemAction::Pong, MessageStatus::Success, []);
+            pong.correlation_id = message.correlation_id;
+            let _ = self.write(&pong);
+        }
+    }
+    // hand a pushed message to the consumer function bound to its group and topic.
+    // if acks were negotiated, the result of the consumer function is sent back to the broker,
+    // Ok acknowledges the message and Err rejects it with the error as the reason.
+    // a message that cannot be handed to a consumer function is rejected as well.
+    fn consume(&self, mss: Message) {
+        let mut settle = Message::system(SystemAction::Ack, MessageStatus::None, []);
+        settle.group_id = mss.group_id.clone();
+        settle.topic = mss.topic.clone();
+        settle.id = mss.id;
+        let result = if mss.msg_type != MessageType::Business {
+            Err(format!("Unexpected message type {:?}.", mss.msg_type))
+        } else if mss.status != MessageStatus::Success {
+            Err(format!("Unexpected message status {:?}.", mss.status))
+        } else {
+            ConsumerBinder::get((mss.group_id.to_string(), mss.topic.name.to_string()))
+                .and_then(|fn_consumer| fn_consumer(mss))
+        };
+        if let Err(e) = result {
+            // consumption fail
+            settle.action = SystemAction::Nack;
+            settle.set_text(e);
+        }
+        let acks = self
+            .session
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|session| session.supports(Capabilities::ACKS));
+        if acks {
+            let _ = self.write(&settle);
+        }
+    }
+    // write a single message frame to the server,
+    // compressed if the session negotiated compression.
+    fn write(&self, message: &Message) -> io::Result<()> {
+        let protocol = &mut Protocol::default();
+        protocol.head.correlation_id = message.correlation_id;
+        protocol.insert_message(message.to_messagedto());
+        let _ = protocol.ready();
+        protocol.compress_for(self.session.read().unwrap().as_ref());
+        let protocol_buf = protocol.to_byte_vec();
+        let _guard = self.write_lock.lock().unwrap();
+        net::write_all(&self.stream, &protocol_buf)
     }
 }
 
-fn would_block(err: &io::Error) -> bool {
-    err.kind() == io::ErrorKind::WouldBlock
-}
-
-fn interrupted(err: &io::Error) -> bool {
-    err.kind() == io::ErrorKind::Interrupted
+// local port of the connection, 0 if unknown.
+fn local_port(stream: &TcpStream) -> u16 {
+    stream.local_addr().map(|addr| addr.port()).unwrap_or(0)
 }
@@ -1,8 +1,12 @@
-use std::sync::Mutex;
+use std::{sync::Mutex, time::Duration};
 
 use lazy_static::lazy_static;
 
 lazy_static! {
     // message queue server listener port
     pub static ref LISTENER_PORT: Mutex<String> = Mutex::new("127.0.0.1:8080".to_string());
+    // the server is pinged after the connection was idle for this interval
+    pub static ref HEARTBEAT_INTERVAL: Mutex<Duration> = Mutex::new(Duration::from_secs(10));
+    // the connection is closed if the server sent nothing for this long
+    pub static ref HEARTBEAT_TIMEOUT: Mutex<Duration> = Mutex::new(Duration::from_secs(30));
 }
@@ -0,0 +1,51 @@
+use std::{fmt, io};
+
+use luminmq_core::error::{ErrorCode, LuminMQError};
+
+/// error returned by the luminmq client.
+#[derive(Debug)]
+pub enum ClientError {
+    // the underlying connection failed
+    Io(io::Error),
+    // the connection was closed before a response arrived
+    ConnectionClosed,
+    // the server answered the request with an error frame
+    Server(LuminMQError),
+    // the server answered with an unexpected response
+    InvalidResponse(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "Connection error: {}", e),
+            ClientError::ConnectionClosed => write!(f, "Connection is closed."),
+            ClientError::Server(e) => write!(f, "Request failed: {}", e),
+            ClientError::InvalidResponse(e) => write!(f, "Invalid response: {}", e),
+        }
+    }
+}
+
+impl ClientError {
+    // the stable error code, if the server reported the error.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            ClientError::Server(e) => Some(e.code),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+impl From<LuminMQError> for ClientError {
+    fn from(e: LuminMQError) -> Self {
+        ClientError::Server(e)
+    }
+}
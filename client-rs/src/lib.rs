@@ -1,2 +1,3 @@
 pub mod client;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod error;
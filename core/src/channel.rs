@@ -1,17 +1,85 @@
 use std::{
-    collections::VecDeque,
-    sync::{Arc, RwLock},
-    thread,
-    time::Duration,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
+use lazy_static::lazy_static;
+use mio::Token;
+use rand::seq::IndexedRandom;
+use tracing::{Level, event};
+
 use crate::{
-    group::GroupMode,
-    msg::Message,
+    group::{GroupMode, Groups},
+    handshake::Capabilities,
+    msg::{Message, MessageStatus},
+    session::Session,
+    tool::time::now_millis,
     topic::Topic,
-    types::{ConnectionPool, ConnectionPoolAndGroupBind},
+    types::{ConnectionPool, ConnectionPoolAndGroupBind, ConnectionSession},
 };
 
+// maximum number of messages pushed to a consumer in one batch frame.
+const MAX_PUSH_BATCH_SIZE: usize = 32;
+// prefix of the headers set by the broker, producers cannot set them.
+pub const RESERVED_HEADER_PREFIX: &str = "x-luminmq-";
+// header of a redelivered or dead-lettered message, the reason of the last failed delivery.
+pub const HEADER_LAST_FAILURE: &str = "x-luminmq-last-failure";
+// header of a dead-lettered or expired message, the topic it was sent to.
+pub const HEADER_ORIGINAL_TOPIC: &str = "x-luminmq-original-topic";
+// header of a dead-lettered message, the number of delivery attempts.
+pub const HEADER_DELIVERY_ATTEMPTS: &str = "x-luminmq-delivery-attempts";
+// header of an expired message routed to the expiry channel, milliseconds since the unix epoch
+// at which it expired.
+pub const HEADER_EXPIRED_AT: &str = "x-luminmq-expired-at";
+// default number of (producer id, sequence) pairs a channel remembers to drop retried sends.
+pub const DEFAULT_DEDUP_WINDOW: usize = 4096;
+// maximum number of priority levels of a channel.
+pub const MAX_PRIORITY_LEVELS: u8 = 16;
+// interval of the sweeps removing expired messages from the queue.
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// interval of the scans for acks that timed out and expired messages skipped by dequeues.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_millis(100);
+// pause of a push channel that found nothing to deliver.
+const IDLE_INTERVAL: Duration = Duration::from_millis(10);
+
+lazy_static! {
+    // delivery options of push channels.
+    static ref DELIVERY_OPTIONS: RwLock<DeliveryOptions> =
+        RwLock::new(DeliveryOptions::default());
+}
+
+/// options deciding how pushed messages are delivered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeliveryOptions {
+    // messages not acknowledged within this time are redelivered
+    pub ack_timeout: Duration,
+}
+
+impl DeliveryOptions {
+    pub fn new(ack_timeout: Duration) -> Self {
+        Self { ack_timeout }
+    }
+    // global delivery options
+    pub fn get() -> Self {
+        *DELIVERY_OPTIONS.read().unwrap()
+    }
+    pub fn set(options: DeliveryOptions) {
+        *DELIVERY_OPTIONS.write().unwrap() = options;
+    }
+}
+
+impl Default for DeliveryOptions {
+    fn default() -> Self {
+        Self {
+            ack_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
 /// Consumption mode for messages within a channel.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ChannelMode {
@@ -23,6 +91,60 @@ pub enum ChannelMode {
     None,
 }
 
+/// per-topic channel configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelConfig {
+    // a message delivered this many times without an ack is moved to the dead-letter channel,
+    // 0 redelivers without limit
+    pub max_delivery_attempts: u32,
+    // dead-letter topic in the same group, "<topic>.dlq" if not set
+    pub dead_letter_topic: Option<String>,
+    // time to live of messages without their own, None keeps them until consumed
+    pub default_ttl: Option<Duration>,
+    // topic in the same group receiving expired messages, None discards them
+    pub expiry_topic: Option<String>,
+    // number of priority levels, higher levels are delivered first and each level is FIFO.
+    // 0 and 1 keep a single FIFO queue, at most MAX_PRIORITY_LEVELS
+    pub priority_levels: u8,
+    // number of recent (producer id, sequence) pairs remembered to drop duplicate sends of
+    // idempotent producers, 0 disables deduplication
+    pub dedup_window: usize,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            max_delivery_attempts: 0,
+            dead_letter_topic: None,
+            default_ttl: None,
+            expiry_topic: None,
+            priority_levels: 0,
+            dedup_window: DEFAULT_DEDUP_WINDOW,
+        }
+    }
+}
+
+impl ChannelConfig {
+    pub fn new(max_delivery_attempts: u32, dead_letter_topic: Option<String>) -> Self {
+        Self {
+            max_delivery_attempts,
+            dead_letter_topic,
+            ..Default::default()
+        }
+    }
+    // the dead-letter topic of a channel, None if dead-lettering is disabled.
+    pub fn dead_letter_topic(&self, topic: &str) -> Option<String> {
+        if self.max_delivery_attempts == 0 {
+            return None;
+        }
+        Some(
+            self.dead_letter_topic
+                .clone()
+                .unwrap_or_else(|| format!("{}.dlq", topic)),
+        )
+    }
+}
+
 /// message channel
 pub struct Channel {
     pub topic: Topic,
@@ -30,21 +152,74 @@ pub struct Channel {
     pub mode: ChannelMode,
     group_mode: GroupMode,
     message_queue: Arc<RwLock<Queue>>,
+    // messages pushed to consumers that negotiated acks, waiting for their ack.
+    in_flight: Arc<RwLock<InFlight>>,
+    pub config: ChannelConfig,
+    // number of messages that expired before they were consumed.
+    expired: Arc<AtomicU64>,
+    // recent sends of idempotent producers.
+    dedup: DedupWindow,
 }
 impl Channel {
     pub fn new(topic: String, group_id: String, mode: ChannelMode, group_mode: GroupMode) -> Self {
+        Self::with_config(topic, group_id, mode, group_mode, ChannelConfig::default())
+    }
+    pub fn with_config(
+        topic: String,
+        group_id: String,
+        mode: ChannelMode,
+        group_mode: GroupMode,
+        config: ChannelConfig,
+    ) -> Self {
         Self {
-            message_queue: Arc::new(RwLock::new(Queue::new())),
+            message_queue: Arc::new(RwLock::new(Queue::with_levels(config.priority_levels))),
+            in_flight: Arc::new(RwLock::new(InFlight::default())),
             mode: mode,
             group_mode: group_mode,
             topic: Topic::new(topic),
             group_id: group_id,
+            expired: Arc::new(AtomicU64::new(0)),
+            dedup: DedupWindow::new(config.dedup_window),
+            config,
         }
     }
     // first in
-    pub fn enqueue(&mut self, message: Message) {
+    pub fn enqueue(&mut self, mut message: Message) {
+        self.set_expiry(&mut message, now_millis());
         self.message_queue.write().unwrap().enqueue(message);
     }
+    // enqueue several messages atomically, keeping their order.
+    pub fn enqueue_batch(&mut self, mut messages: Vec<Message>) {
+        let now = now_millis();
+        for message in messages.iter_mut() {
+            self.set_expiry(message, now);
+        }
+        self.message_queue.write().unwrap().enqueue_batch(messages);
+    }
+    // the id of the first send of the same producer id and sequence, None if the message is new.
+    // new messages of idempotent producers are remembered, the message id must be assigned.
+    pub fn deduplicate(&mut self, message: &Message) -> Option<u64> {
+        match &message.producer_id {
+            Some(producer_id) => self.dedup.check(producer_id, message.sequence, message.id),
+            None => None,
+        }
+    }
+    // the expiry of a message starts when it enters the queue.
+    fn set_expiry(&self, message: &mut Message, now: u64) {
+        if message.expires_at != 0 {
+            return;
+        }
+        let ttl = match message.ttl {
+            0 => self
+                .config
+                .default_ttl
+                .map_or(0, |ttl| ttl.as_millis() as u64),
+            ttl => ttl,
+        };
+        if ttl > 0 {
+            message.expires_at = now.saturating_add(ttl);
+        }
+    }
     // first out
     pub fn dequeue(&mut self) -> Option<Message> {
         self.message_queue.write().unwrap().dequeue()
@@ -57,103 +232,712 @@ impl Channel {
     pub fn message_num(&self) -> u64 {
         self.message_queue.read().unwrap().len().try_into().unwrap()
     }
+    // number of messages waiting for an ack.
+    pub fn in_flight_num(&self) -> u64 {
+        self.in_flight.read().unwrap().len().try_into().unwrap()
+    }
+    // number of messages that expired before they were consumed.
+    pub fn expired_num(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+    // the consumer processed the message, it is not delivered again.
+    // returns false if the message is not in flight to the connection.
+    pub fn ack(&self, id: u64, token: Token) -> bool {
+        self.in_flight.write().unwrap().remove(id, token).is_some()
+    }
+    // the consumer failed to process the message, it is redelivered immediately.
+    // returns the message if it reached the max delivery attempts, move it with `dead_letter`
+    // once every group and channel guard is dropped.
+    pub fn nack(&self, id: u64, token: Token, reason: &str) -> Vec<Message> {
+        let message = self.in_flight.write().unwrap().remove(id, token);
+        match message {
+            Some(message) => {
+                let reason = if reason.is_empty() {
+                    "Rejected."
+                } else {
+                    reason
+                };
+                self.redeliver(vec![message], reason)
+            }
+            None => Vec::new(),
+        }
+    }
+    // redeliver every message in flight to a closed connection.
+    // returns the messages that reached the max delivery attempts, see `nack`.
+    pub fn release(&self, token: Token) -> Vec<Message> {
+        let messages = self.in_flight.write().unwrap().release(token);
+        self.redeliver(messages, "Connection closed.")
+    }
+    // redeliver the messages whose ack timed out before now, like the channel loop does.
+    // returns the messages that reached the max delivery attempts, see `nack`.
+    pub fn time_out(&self, now: Instant) -> Vec<Message> {
+        time_out(
+            &self.message_queue,
+            &self.in_flight,
+            &self.topic.name,
+            &self.config,
+            now,
+        )
+    }
+    // take the next messages the channel loop pushes to a cluster of consumers, `acks` tells
+    // which consumers negotiated acks. the messages are taken out of the queue and are lost
+    // unless the caller delivers them, only tests drive deliveries without connections.
+    #[doc(hidden)]
+    pub fn take_deliveries(
+        &self,
+        tokens: &[Token],
+        acks: impl Fn(&Token) -> bool,
+    ) -> Vec<(Token, Vec<Message>)> {
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+        take_deliveries(&self.message_queue, &self.in_flight, tokens, acks, |_| {
+            false
+        })
+    }
+    fn redeliver(&self, messages: Vec<Message>, reason: &str) -> Vec<Message> {
+        redeliver(
+            &self.message_queue,
+            &self.topic.name,
+            &self.config,
+            messages,
+            reason,
+        )
+    }
     pub fn start(&self) {
         let group_id = self.group_id.clone();
         let topic = self.topic.name.clone();
         let channel_mode = self.mode.clone();
         let queue = Arc::clone(&self.message_queue);
+        let in_flight = Arc::clone(&self.in_flight);
         let group_mode = self.group_mode.clone();
+        let config = self.config.clone();
+        let expired_num = Arc::clone(&self.expired);
         tokio::spawn(async move {
+            let mut last_sweep = Instant::now();
+            let mut last_maintenance = Instant::now();
             loop {
+                if last_maintenance.elapsed() >= MAINTENANCE_INTERVAL {
+                    last_maintenance = Instant::now();
+                    // messages whose ack timed out are delivered again
+                    dead_letter(time_out(
+                        &queue,
+                        &in_flight,
+                        &topic,
+                        &config,
+                        Instant::now(),
+                    ));
+                    // expired messages are skipped by dequeues and swept from the queue
+                    let expired = {
+                        let mut queue = queue.write().unwrap();
+                        if last_sweep.elapsed() >= EXPIRY_SWEEP_INTERVAL {
+                            queue.sweep(now_millis());
+                            last_sweep = Instant::now();
+                        }
+                        queue.take_expired()
+                    };
+                    if !expired.is_empty() {
+                        expired_num.fetch_add(expired.len() as u64, Ordering::Relaxed);
+                        expire(&group_id, &topic, &config, expired);
+                    }
+                }
                 // The mode of the group to which the current pipeline belongs
-                match group_mode {
+                let delivered = match group_mode {
                     GroupMode::Broadcast => match channel_mode {
                         ChannelMode::Push => {
                             // test
-                            thread::sleep(Duration::from_millis(1000));
+                            tokio::time::sleep(Duration::from_millis(1000)).await;
                             let token_list = ConnectionPoolAndGroupBind::get_token_list((
                                 group_id.clone(),
                                 topic.clone(),
                             ));
                             token_list.iter().for_each(|token| {
-                                ConnectionPool::handle(token, |stream| {
-                                    match queue.write().unwrap().dequeue() {
-                                        Some(msg) => {
-                                            let _ = msg.writer(stream);
-                                        }
-                                        None => {
-                                            // there are no messages in the queue.
-                                        }
-                                    }
-                                });
+                                push(&queue, &in_flight, token);
                             });
+                            true
                         }
-                        ChannelMode::Pull => (),
-                        ChannelMode::None => (),
+                        ChannelMode::Pull => false,
+                        ChannelMode::None => false,
                     },
                     GroupMode::Cluster => match channel_mode {
                         ChannelMode::Push => {
-                            // Push messages randomly to consumer clusters.
-                            match ConnectionPoolAndGroupBind::get_random_token((
+                            // Push messages randomly to consumer clusters, messages with a key
+                            // stick to one consumer.
+                            let token_list = ConnectionPoolAndGroupBind::get_token_list((
                                 group_id.clone(),
                                 topic.clone(),
-                            )) {
-                                Some(token) => {
-                                    ConnectionPool::handle(&token, |stream| {
-                                        match queue.write().unwrap().dequeue() {
-                                            Some(msg) => {
-                                                let _ = msg.writer(stream);
-                                            }
-                                            None => {
-                                                // there are no messages in the queue.
-                                            }
-                                        }
-                                    });
-                                }
-                                None => {
-                                    // No connection exists
-                                }
-                            }
+                            ));
+                            !token_list.is_empty() && dispatch(&queue, &in_flight, &token_list)
                         }
-                        ChannelMode::Pull => (),
-                        ChannelMode::None => (),
+                        ChannelMode::Pull => false,
+                        ChannelMode::None => false,
                     },
+                };
+                // pull channels only need the scans, push channels wait for messages or consumers
+                match channel_mode {
+                    ChannelMode::Push if delivered => tokio::task::yield_now().await,
+                    ChannelMode::Push => tokio::time::sleep(IDLE_INTERVAL).await,
+                    _ => tokio::time::sleep(MAINTENANCE_INTERVAL).await,
                 }
             }
         });
     }
 }
 
+// whether the connection negotiated the capabilities.
+fn supports(session: &Option<Session>, capabilities: Capabilities) -> bool {
+    session
+        .as_ref()
+        .is_some_and(|session| session.supports(capabilities))
+}
+
+// push ready messages to a consumer.
+fn push(queue: &Arc<RwLock<Queue>>, in_flight: &Arc<RwLock<InFlight>>, token: &Token) {
+    if ConnectionPool::is_congested(token) {
+        // the consumer has not read the messages pushed before
+        return;
+    }
+    let batching = supports(&ConnectionSession::get(token), Capabilities::BATCHING);
+    let messages = {
+        let mut queue = queue.write().unwrap();
+        if batching {
+            queue.dequeue_batch(MAX_PUSH_BATCH_SIZE)
+        } else {
+            queue.dequeue().into_iter().collect()
+        }
+    };
+    deliver(queue, in_flight, token, messages);
+}
+
+// push ready messages to a cluster of consumers, see `take_deliveries`.
+// returns false if there was no message to push.
+fn dispatch(
+    queue: &Arc<RwLock<Queue>>,
+    in_flight: &Arc<RwLock<InFlight>>,
+    tokens: &[Token],
+) -> bool {
+    let acks: HashMap<Token, bool> = tokens
+        .iter()
+        .map(|token| {
+            let session = ConnectionSession::get(token);
+            (*token, supports(&session, Capabilities::ACKS))
+        })
+        .collect();
+    let congested: HashSet<Token> = tokens
+        .iter()
+        .filter(|token| ConnectionPool::is_congested(token))
+        .copied()
+        .collect();
+    let deliveries = take_deliveries(
+        queue,
+        in_flight,
+        tokens,
+        |token| acks[token],
+        |token| congested.contains(token),
+    );
+    if deliveries.is_empty() {
+        return false;
+    }
+    for (token, messages) in deliveries {
+        write(queue, in_flight, &token, messages, acks[&token]);
+    }
+    true
+}
+
+// take ready messages for a cluster of consumers. messages without a key go to a random consumer,
+// messages with a key go to the consumer of the key. while a message of a key is in flight to a
+// consumer that negotiated acks, the later messages of the key wait in the queue.
+// consumers that are congested get no messages, the messages of their keys wait in the queue.
+fn take_deliveries(
+    queue: &Arc<RwLock<Queue>>,
+    in_flight: &Arc<RwLock<InFlight>>,
+    tokens: &[Token],
+    acks: impl Fn(&Token) -> bool,
+    congested: impl Fn(&Token) -> bool,
+) -> Vec<(Token, Vec<Message>)> {
+    let ready: Vec<Token> = tokens
+        .iter()
+        .filter(|token| !congested(token))
+        .copied()
+        .collect();
+    if ready.is_empty() {
+        return Vec::new();
+    }
+    let busy_keys = in_flight.read().unwrap().keys();
+    let mut selected_keys = HashSet::new();
+    let messages = queue
+        .write()
+        .unwrap()
+        .dequeue_batch_by(MAX_PUSH_BATCH_SIZE, |message| match &message.key {
+            None => true,
+            Some(key) => {
+                let token = ConnectionPoolAndGroupBind::route(key, tokens).unwrap();
+                !congested(&token)
+                    && (!acks(&token)
+                        || (!busy_keys.contains(key) && selected_keys.insert(key.clone())))
+            }
+        });
+    if messages.is_empty() {
+        return Vec::new();
+    }
+    let random = *ready.choose(&mut rand::rng()).unwrap();
+    let mut deliveries: Vec<(Token, Vec<Message>)> = Vec::new();
+    for message in messages {
+        let token = match &message.key {
+            Some(key) => ConnectionPoolAndGroupBind::route(key, tokens).unwrap(),
+            None => random,
+        };
+        match deliveries.iter_mut().find(|(t, _)| *t == token) {
+            Some((_, messages)) => messages.push(message),
+            None => deliveries.push((token, vec![message])),
+        }
+    }
+    for (token, messages) in deliveries.iter_mut() {
+        track(in_flight, token, messages, acks(token));
+    }
+    deliveries
+}
+
+// write messages to a consumer, see `track` and `write`.
+fn deliver(
+    queue: &Arc<RwLock<Queue>>,
+    in_flight: &Arc<RwLock<InFlight>>,
+    token: &Token,
+    mut messages: Vec<Message>,
+) {
+    if messages.is_empty() {
+        // there are no messages in the queue.
+        return;
+    }
+    let acks = supports(&ConnectionSession::get(token), Capabilities::ACKS);
+    track(in_flight, token, &mut messages, acks);
+    write(queue, in_flight, token, messages, acks);
+}
+
+// prepare messages taken for a consumer. pushed messages are consumable, whatever status the
+// producer sent. if the consumer negotiated acks, the messages stay in flight until they are
+// acknowledged, they are tracked before the write since the consumer may settle them first.
+fn track(in_flight: &Arc<RwLock<InFlight>>, token: &Token, messages: &mut [Message], acks: bool) {
+    for message in messages.iter_mut() {
+        message.status = MessageStatus::Success;
+        if acks {
+            message.delivery_attempts += 1;
+        }
+    }
+    if acks {
+        let deadline = Instant::now() + DeliveryOptions::get().ack_timeout;
+        let mut in_flight = in_flight.write().unwrap();
+        for message in messages.iter() {
+            in_flight.insert(message.clone(), *token, deadline);
+        }
+    }
+}
+
+// write tracked messages to a consumer. if the consumer negotiated batching, several messages
+// are sent in one batch frame. the messages go back to the queue if the connection is gone or
+// its outbound buffer is full.
+fn write(
+    queue: &Arc<RwLock<Queue>>,
+    in_flight: &Arc<RwLock<InFlight>>,
+    token: &Token,
+    messages: Vec<Message>,
+    acks: bool,
+) {
+    let session = ConnectionSession::get(token);
+    let batching = supports(&session, Capabilities::BATCHING);
+    let written = ConnectionPool::handle(token, |connection| {
+        if messages.len() == 1 || !batching {
+            // once a write fails the remaining messages are not written, their order is kept
+            let mut failed = false;
+            messages
+                .iter()
+                .map(|message| {
+                    failed = failed
+                        || message
+                            .session_writer(connection, session.as_ref())
+                            .is_err();
+                    !failed
+                })
+                .collect()
+        } else {
+            let ok = Message::batch_writer(&messages, connection, session.as_ref()).is_ok();
+            vec![ok; messages.len()]
+        }
+    })
+    .unwrap_or_else(|| vec![false; messages.len()]);
+    // messages that were not written are delivered again, they did not use a delivery attempt
+    let failed: Vec<Message> = messages
+        .into_iter()
+        .zip(written)
+        .filter(|(_, written)| !written)
+        .filter_map(|(mut message, _)| {
+            if acks {
+                in_flight.write().unwrap().remove(message.id, *token)?;
+                message.delivery_attempts -= 1;
+            }
+            Some(message)
+        })
+        .collect();
+    if !failed.is_empty() {
+        queue.write().unwrap().requeue(failed);
+    }
+}
+
+// redeliver the messages whose ack timed out before now.
+fn time_out(
+    queue: &Arc<RwLock<Queue>>,
+    in_flight: &Arc<RwLock<InFlight>>,
+    topic: &str,
+    config: &ChannelConfig,
+    now: Instant,
+) -> Vec<Message> {
+    let expired = in_flight.write().unwrap().expired(now);
+    if expired.is_empty() {
+        return Vec::new();
+    }
+    redeliver(queue, topic, config, expired, "Ack timed out.")
+}
+
+// put messages back into the queue of their channel after a failed delivery.
+// messages that reached the max delivery attempts are returned addressed to the dead-letter channel,
+// the dead-letter channel is locked by `dead_letter` so no channel guard is held while moving them.
+fn redeliver(
+    queue: &Arc<RwLock<Queue>>,
+    topic: &str,
+    config: &ChannelConfig,
+    messages: Vec<Message>,
+    reason: &str,
+) -> Vec<Message> {
+    let mut requeue = Vec::new();
+    let mut dead_letters = Vec::new();
+    for mut message in messages {
+        message.set_header(HEADER_LAST_FAILURE, reason);
+        if config.max_delivery_attempts == 0
+            || message.delivery_attempts < config.max_delivery_attempts
+        {
+            requeue.push(message);
+            continue;
+        }
+        message.set_header(HEADER_ORIGINAL_TOPIC, topic);
+        message.set_header(
+            HEADER_DELIVERY_ATTEMPTS,
+            message.delivery_attempts.to_string(),
+        );
+        message.topic = Topic::new(config.dead_letter_topic(topic).unwrap());
+        message.delivery_attempts = 0;
+        // dead-lettered messages are kept by the ttl of the dead-letter topic
+        message.ttl = 0;
+        message.expires_at = 0;
+        dead_letters.push(message);
+    }
+    if !requeue.is_empty() {
+        queue.write().unwrap().requeue(requeue);
+    }
+    dead_letters
+}
+
+// move messages returned by `nack` and `release` to their dead-letter channel.
+// must be called without holding a group or channel guard, the dead-letter channel is locked here.
+pub fn dead_letter(messages: Vec<Message>) {
+    for mut message in messages {
+        let channel =
+            match Groups::get_channel(message.group_id.clone(), message.topic.name.clone()) {
+                Ok(channel) => channel,
+                Err(e) => {
+                    // never lose a message, keep redelivering it from its original channel
+                    event!(Level::WARN, "dead-letter channel is missing: {}", e);
+                    let original_topic = match message.header(HEADER_ORIGINAL_TOPIC) {
+                        Some(original_topic) => original_topic.to_string(),
+                        None => {
+                            event!(Level::WARN, "dead letter without its topic is dropped");
+                            continue;
+                        }
+                    };
+                    message.topic = Topic::new(original_topic);
+                    match Groups::get_channel(message.group_id.clone(), message.topic.name.clone())
+                    {
+                        Ok(channel) => channel,
+                        Err(e) => {
+                            event!(Level::WARN, "dead letter is dropped: {}", e);
+                            continue;
+                        }
+                    }
+                }
+            };
+        channel.write().unwrap().enqueue(message);
+    }
+}
+
+// route expired messages to the expiry channel, or discard them if it is not configured.
+fn expire(group_id: &str, topic: &str, config: &ChannelConfig, messages: Vec<Message>) {
+    let expiry_topic = match &config.expiry_topic {
+        Some(expiry_topic) if expiry_topic != topic => expiry_topic,
+        _ => return,
+    };
+    let channel = match Groups::get_channel(group_id.to_string(), expiry_topic.clone()) {
+        Ok(channel) => channel,
+        Err(e) => {
+            event!(Level::WARN, "expiry channel is missing: {}", e);
+            return;
+        }
+    };
+    let mut channel = channel.write().unwrap();
+    for mut message in messages {
+        message.set_header(HEADER_ORIGINAL_TOPIC, topic);
+        message.set_header(HEADER_EXPIRED_AT, message.expires_at.to_string());
+        message.topic = Topic::new(expiry_topic.clone());
+        message.ttl = 0;
+        message.expires_at = 0;
+        channel.enqueue(message);
+    }
+}
+
+// the most recent sends of idempotent producers, the oldest are forgotten first.
+struct DedupWindow {
+    capacity: usize,
+    // oldest first
+    order: VecDeque<(String, u64)>,
+    // k: (producer id, sequence) v: message id
+    ids: HashMap<(String, u64), u64>,
+}
+impl DedupWindow {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::new(),
+            ids: HashMap::new(),
+        }
+    }
+    // the message id of a remembered send, or remember the send and return None.
+    fn check(&mut self, producer_id: &str, sequence: u64, id: u64) -> Option<u64> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let k = (producer_id.to_string(), sequence);
+        if let Some(id) = self.ids.get(&k) {
+            return Some(*id);
+        }
+        if self.order.len() >= self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.ids.remove(&oldest);
+        }
+        self.order.push_back(k.clone());
+        self.ids.insert(k, id);
+        None
+    }
+}
+
+// a message pushed to a consumer.
+struct Delivery {
+    message: Message,
+    token: Token,
+    // the message is redelivered if it is not acknowledged until then
+    deadline: Instant,
+}
+
+// messages pushed to consumers and not acknowledged yet.
+#[derive(Default)]
+struct InFlight {
+    // k: message id
+    deliveries: HashMap<u64, Delivery>,
+    // k: key v: number of messages of the key in flight
+    keys: HashMap<String, usize>,
+}
+impl InFlight {
+    fn insert(&mut self, message: Message, token: Token, deadline: Instant) {
+        if let Some(key) = &message.key {
+            *self.keys.entry(key.clone()).or_default() += 1;
+        }
+        self.deliveries.insert(
+            message.id,
+            Delivery {
+                message,
+                token,
+                deadline,
+            },
+        );
+    }
+    // remove a message in flight to the connection.
+    fn remove(&mut self, id: u64, token: Token) -> Option<Message> {
+        match self.deliveries.get(&id) {
+            Some(delivery) if delivery.token == token => {
+                let message = self.deliveries.remove(&id).unwrap().message;
+                self.forget_key(&message);
+                Some(message)
+            }
+            _ => None,
+        }
+    }
+    fn forget_key(&mut self, message: &Message) {
+        if let Some(key) = &message.key
+            && let Some(n) = self.keys.get_mut(key)
+        {
+            *n -= 1;
+            if *n == 0 {
+                self.keys.remove(key);
+            }
+        }
+    }
+    // keys with a message in flight.
+    fn keys(&self) -> HashSet<String> {
+        self.keys.keys().cloned().collect()
+    }
+    // remove the messages whose ack timed out.
+    fn expired(&mut self, now: Instant) -> Vec<Message> {
+        self.drain(|delivery| delivery.deadline <= now)
+    }
+    // remove the messages in flight to the connection.
+    fn release(&mut self, token: Token) -> Vec<Message> {
+        self.drain(|delivery| delivery.token == token)
+    }
+    // remove the matching messages, ordered by message id.
+    fn drain(&mut self, mut f: impl FnMut(&Delivery) -> bool) -> Vec<Message> {
+        if self.deliveries.is_empty() {
+            return Vec::new();
+        }
+        let ids: Vec<u64> = self
+            .deliveries
+            .iter()
+            .filter(|(_, delivery)| f(delivery))
+            .map(|(id, _)| *id)
+            .collect();
+        let mut messages: Vec<Message> = ids
+            .iter()
+            .filter_map(|id| self.deliveries.remove(id))
+            .map(|delivery| delivery.message)
+            .collect();
+        for message in messages.iter() {
+            self.forget_key(message);
+        }
+        messages.sort_by_key(|message| message.id);
+        messages
+    }
+    fn len(&self) -> usize {
+        self.deliveries.len()
+    }
+}
+
+// message queue of a channel, one FIFO queue per priority level.
 struct Queue {
-    queue: VecDeque<Message>,
+    // lowest priority first
+    levels: Vec<VecDeque<Message>>,
+    // expired messages skipped or swept, waiting to be counted and routed.
+    expired: Vec<Message>,
 }
 impl Queue {
-    pub fn new() -> Self {
+    pub fn with_levels(levels: u8) -> Self {
         Self {
-            queue: VecDeque::new(),
+            levels: (0..levels.clamp(1, MAX_PRIORITY_LEVELS))
+                .map(|_| VecDeque::new())
+                .collect(),
+            expired: Vec::new(),
         }
     }
+    // the queue of the priority level of a message
+    fn level(&mut self, message: &Message) -> &mut VecDeque<Message> {
+        let level = (message.priority as usize).min(self.levels.len() - 1);
+        &mut self.levels[level]
+    }
     // first in
     pub fn enqueue(&mut self, message: Message) {
-        self.queue.push_back(message);
+        self.level(&message).push_back(message);
     }
-    // first out
+    // enqueue several messages, keeping their order
+    pub fn enqueue_batch(&mut self, messages: Vec<Message>) {
+        for message in messages {
+            self.enqueue(message);
+        }
+    }
+    // put messages to redeliver back in front of their level, keeping their order
+    pub fn requeue(&mut self, messages: Vec<Message>) {
+        for message in messages.into_iter().rev() {
+            self.level(&message).push_front(message);
+        }
+    }
+    // first out from the highest level, expired messages are skipped
     pub fn dequeue(&mut self) -> Option<Message> {
-        self.queue.pop_front()
+        let now = now_millis();
+        for level in self.levels.iter_mut().rev() {
+            while let Some(message) = level.pop_front() {
+                if !message.is_expired(now) {
+                    return Some(message);
+                }
+                self.expired.push(message);
+            }
+        }
+        None
+    }
+    // first out from the highest level, up to max messages, expired messages are skipped
+    pub fn dequeue_batch(&mut self, max: usize) -> Vec<Message> {
+        let mut messages = Vec::new();
+        while messages.len() < max {
+            match self.dequeue() {
+                Some(message) => messages.push(message),
+                None => break,
+            }
+        }
+        messages
+    }
+    // first out from the highest level, up to max messages accepted by f. the other messages
+    // keep their place, expired messages are skipped
+    pub fn dequeue_batch_by(
+        &mut self,
+        max: usize,
+        mut f: impl FnMut(&Message) -> bool,
+    ) -> Vec<Message> {
+        let now = now_millis();
+        let mut messages = Vec::new();
+        for level in self.levels.iter_mut().rev() {
+            if messages.len() >= max {
+                break;
+            }
+            let mut kept = VecDeque::new();
+            while let Some(message) = level.pop_front() {
+                if messages.len() >= max {
+                    kept.push_back(message);
+                    kept.extend(level.drain(..));
+                    break;
+                }
+                if message.is_expired(now) {
+                    self.expired.push(message);
+                } else if f(&message) {
+                    messages.push(message);
+                } else {
+                    kept.push_back(message);
+                }
+            }
+            *level = kept;
+        }
+        messages
+    }
+    // move every expired message out of the queue
+    pub fn sweep(&mut self, now: u64) {
+        for level in self.levels.iter_mut() {
+            if !level.iter().any(|message| message.is_expired(now)) {
+                continue;
+            }
+            let (expired, queue): (Vec<Message>, Vec<Message>) =
+                level.drain(..).partition(|message| message.is_expired(now));
+            *level = queue.into();
+            self.expired.extend(expired);
+        }
+    }
+    // the expired messages removed from the queue
+    pub fn take_expired(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.expired)
     }
     // is empty
     pub fn is_empty(&self) -> bool {
-        self.queue.is_empty()
+        self.levels.iter().all(VecDeque::is_empty)
     }
     // len
     pub fn len(&self) -> usize {
-        self.queue.len()
+        self.levels.iter().map(VecDeque::len).sum()
     }
 }
 impl Default for Queue {
     fn default() -> Self {
-        Self {
-            queue: VecDeque::new(),
-        }
+        Self::with_levels(1)
     }
 }
@@ -0,0 +1,111 @@
+/// frame body compression.
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    // compression options used when writing frames to peers that negotiated compression.
+    static ref COMPRESSION_OPTIONS: RwLock<CompressionOptions> =
+        RwLock::new(CompressionOptions::default());
+}
+
+/// compression codec of a frame body, flagged in the protocol header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionCodec {
+    // the body is not compressed
+    None,
+    // fast compression
+    Lz4,
+    // dense compression
+    Zstd,
+}
+
+impl CompressionCodec {
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(CompressionCodec::None),
+            1 => Some(CompressionCodec::Lz4),
+            2 => Some(CompressionCodec::Zstd),
+            _ => None,
+        }
+    }
+    // compress bytes with the codec.
+    pub fn compress(&self, bytes: &[u8], level: i32) -> Vec<u8> {
+        match self {
+            CompressionCodec::None => bytes.to_vec(),
+            CompressionCodec::Lz4 => lz4_flex::compress_prepend_size(bytes),
+            CompressionCodec::Zstd => zstd::bulk::compress(bytes, level).unwrap(),
+        }
+    }
+    // decompress bytes with the codec, fails if the result would exceed max_size.
+    pub fn decompress(&self, bytes: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+        match self {
+            CompressionCodec::None => Ok(bytes.to_vec()),
+            CompressionCodec::Lz4 => {
+                if bytes.len() < 4 {
+                    return Err("Invalid lz4 frame body".to_string());
+                }
+                let size = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+                if size > max_size {
+                    return Err(format!(
+                        "Decompressed frame body of {} bytes exceeds the limit of {} bytes",
+                        size, max_size
+                    ));
+                }
+                lz4_flex::decompress_size_prepended(bytes)
+                    .map_err(|e| format!("Lz4 decompression exception: {}", e))
+            }
+            CompressionCodec::Zstd => zstd::bulk::decompress(bytes, max_size)
+                .map_err(|e| format!("Zstd decompression exception: {}", e)),
+        }
+    }
+}
+
+/// options deciding how frame bodies are compressed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionOptions {
+    // codec used for bodies at or above the threshold
+    pub codec: CompressionCodec,
+    // bodies smaller than this number of bytes are sent uncompressed
+    pub threshold: usize,
+    // zstd compression level
+    pub level: i32,
+}
+
+impl CompressionOptions {
+    pub fn new(codec: CompressionCodec, threshold: usize, level: i32) -> Self {
+        Self {
+            codec,
+            threshold,
+            level,
+        }
+    }
+    // the codec to use for a body of the given size.
+    pub fn codec_for(&self, size: usize) -> CompressionCodec {
+        if size < self.threshold {
+            CompressionCodec::None
+        } else {
+            self.codec
+        }
+    }
+    // global compression options
+    pub fn get() -> Self {
+        *COMPRESSION_OPTIONS.read().unwrap()
+    }
+    pub fn set(options: CompressionOptions) {
+        *COMPRESSION_OPTIONS.write().unwrap() = options;
+    }
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            codec: CompressionCodec::Lz4,
+            threshold: 1024,
+            level: 3,
+        }
+    }
+}
@@ -0,0 +1,73 @@
+/// connections of the broker, frames are written through a per-connection outbound buffer.
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use mio::net::TcpStream;
+
+// pushes to a connection pause while more bytes than this wait in its outbound buffer.
+pub const MAX_PENDING_PUSH_SIZE: usize = 1024 * 1024;
+// largest number of bytes buffered for a connection that stopped reading,
+// further frames are rejected until the peer reads or is closed by the heartbeat.
+pub const MAX_OUTBOUND_BUFFER_SIZE: usize = 32 * 1024 * 1024;
+
+/// a connection of the pool. writes never block: a frame is appended to the outbound buffer
+/// as a whole, the buffer is written as far as the socket accepts and the rest is flushed
+/// when the socket becomes writable. frames of concurrent writers never interleave.
+pub struct Connection {
+    stream: TcpStream,
+    outbound: Mutex<VecDeque<u8>>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            outbound: Mutex::new(VecDeque::new()),
+        }
+    }
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+    // queue a whole frame and write as much of the buffer as the socket accepts.
+    // fails without queueing the frame if the outbound buffer is full.
+    pub fn send(&self, frame: &[u8]) -> io::Result<()> {
+        let mut outbound = self.outbound.lock().unwrap();
+        if !outbound.is_empty() && outbound.len() + frame.len() > MAX_OUTBOUND_BUFFER_SIZE {
+            return Err(io::Error::other("Outbound buffer is full."));
+        }
+        outbound.extend(frame);
+        write_buffered(&self.stream, &mut outbound)
+    }
+    // write the buffered frames, called when the socket becomes writable.
+    pub fn flush(&self) -> io::Result<()> {
+        write_buffered(&self.stream, &mut self.outbound.lock().unwrap())
+    }
+    // number of bytes waiting to be written.
+    pub fn pending(&self) -> usize {
+        self.outbound.lock().unwrap().len()
+    }
+    // the peer reads slower than messages are pushed, pushes wait until it catches up.
+    pub fn is_congested(&self) -> bool {
+        self.pending() > MAX_PENDING_PUSH_SIZE
+    }
+}
+
+// write the buffer until it is empty or the socket would block.
+fn write_buffered(mut stream: &TcpStream, outbound: &mut VecDeque<u8>) -> io::Result<()> {
+    while !outbound.is_empty() {
+        let (front, _) = outbound.as_slices();
+        match stream.write(front) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                outbound.drain(..n);
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+/// typed errors reported to peers in error frames.
+use std::fmt;
+
+use bincode::{Decode, Encode};
+
+use crate::{connection::Connection, protocol::Protocol};
+
+/// stable numeric error codes, the numbers are part of the wire protocol and never change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    // an error code unknown to this version
+    Unknown = 0,
+    // the group does not exist
+    UnknownGroup = 1,
+    // the topic does not exist in the group
+    UnknownTopic = 2,
+    // the request does not match the mode of the channel, e.g. pulling from a push channel
+    WrongChannelMode = 3,
+    // there are no messages in the channel
+    QueueEmpty = 4,
+    // the connection is not allowed to perform the request
+    Unauthorized = 5,
+    // the frame exceeds the maximum frame size
+    FrameTooLarge = 6,
+    // no protocol version is supported by both sides
+    UnsupportedVersion = 7,
+    // the handshake must be completed first
+    HandshakeRequired = 8,
+    // the request requires a capability that was not negotiated
+    CapabilityNotNegotiated = 9,
+    // the message or frame is malformed
+    InvalidMessage = 10,
+    // unexpected failure inside the broker
+    Internal = 11,
+    // the request does not match the transaction state of the connection,
+    // e.g. committing without a transaction
+    InvalidTransaction = 12,
+    // the channel configuration is invalid, e.g. a channel dead-lettering into itself
+    InvalidConfig = 13,
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1 => ErrorCode::UnknownGroup,
+            2 => ErrorCode::UnknownTopic,
+            3 => ErrorCode::WrongChannelMode,
+            4 => ErrorCode::QueueEmpty,
+            5 => ErrorCode::Unauthorized,
+            6 => ErrorCode::FrameTooLarge,
+            7 => ErrorCode::UnsupportedVersion,
+            8 => ErrorCode::HandshakeRequired,
+            9 => ErrorCode::CapabilityNotNegotiated,
+            10 => ErrorCode::InvalidMessage,
+            11 => ErrorCode::Internal,
+            12 => ErrorCode::InvalidTransaction,
+            13 => ErrorCode::InvalidConfig,
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
+/// error with a stable code and a human readable message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuminMQError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl LuminMQError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+    pub fn to_errordto(&self) -> ErrorDTO {
+        ErrorDTO {
+            code: self.code.code(),
+            message: self.message.clone(),
+        }
+    }
+    // write the error as an error frame answering the request with the correlation id.
+    pub fn writer(&self, connection: &Connection, correlation_id: u64) -> Result<usize, String> {
+        let protocol = &mut Protocol::default();
+        protocol.head.correlation_id = correlation_id;
+        protocol.insert_error(self.to_errordto());
+        let _ = protocol.ready();
+        let protocol_buf = protocol.to_byte_vec();
+        connection
+            .send(&protocol_buf)
+            .map(|_| protocol_buf.len())
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+impl fmt::Display for LuminMQError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}({}): {}", self.code, self.code.code(), self.message)
+    }
+}
+
+impl std::error::Error for LuminMQError {}
+
+/// error struct used only for data transmission and serialization.
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct ErrorDTO {
+    // stable error code
+    code: u16,
+    // human readable message
+    message: String,
+}
+
+impl ErrorDTO {
+    pub fn to_error(&self) -> LuminMQError {
+        LuminMQError::new(ErrorCode::from_code(self.code), self.message.clone())
+    }
+}
@@ -0,0 +1,160 @@
+/// framing of protocol frames over byte streams.
+/// the tokio codec serves async streams, the frame reader and writer serve any Read or Write,
+/// both use the same decoder as the mio connections of the server and the client.
+use std::io::{self, Read, Write};
+
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    protocol::{FrameLimits, Protocol, ProtocolDecoder},
+    session::Session,
+};
+
+/// tokio codec for protocol frames, use it with tokio_util::codec::Framed.
+pub struct ProtocolCodec {
+    decoder: ProtocolDecoder,
+    // frames are compressed if the session negotiated compression
+    session: Option<Session>,
+}
+
+impl ProtocolCodec {
+    // a codec using the global frame limits.
+    pub fn new() -> Self {
+        Self::with_limits(FrameLimits::get())
+    }
+    pub fn with_limits(limits: FrameLimits) -> Self {
+        Self {
+            decoder: ProtocolDecoder::with_limits(limits),
+            session: None,
+        }
+    }
+    // set the session negotiated by the handshake.
+    pub fn set_session(&mut self, session: Option<Session>) {
+        self.session = session;
+    }
+}
+
+impl Default for ProtocolCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for ProtocolCodec {
+    type Item = Protocol;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Protocol>, io::Error> {
+        loop {
+            // move no more bytes into the decoder than its read buffer limit allows
+            let len = src.len().min(self.decoder.remaining_capacity());
+            self.decoder.extend(&src[..len]);
+            src.advance(len);
+            match self.decoder.next_frame()? {
+                Some(protocol) => return Ok(Some(protocol)),
+                None if src.is_empty() => return Ok(None),
+                None => continue,
+            }
+        }
+    }
+    // the bytes of a partial frame are kept by the decoder, not by the source buffer.
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Protocol>, io::Error> {
+        match self.decode(src)? {
+            Some(protocol) => Ok(Some(protocol)),
+            None if self.decoder.buffered_len() == 0 => Ok(None),
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+impl Encoder<Protocol> for ProtocolCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, mut protocol: Protocol, dst: &mut BytesMut) -> Result<(), io::Error> {
+        protocol.compress_for(self.session.as_ref());
+        dst.extend_from_slice(&protocol.to_byte_vec());
+        Ok(())
+    }
+}
+
+/// reads protocol frames from any blocking reader.
+pub struct FrameReader<R> {
+    reader: R,
+    decoder: ProtocolDecoder,
+}
+
+impl<R: Read> FrameReader<R> {
+    // a frame reader using the global frame limits.
+    pub fn new(reader: R) -> Self {
+        Self::with_limits(reader, FrameLimits::get())
+    }
+    pub fn with_limits(reader: R, limits: FrameLimits) -> Self {
+        Self {
+            reader,
+            decoder: ProtocolDecoder::with_limits(limits),
+        }
+    }
+    // read the next frame, Ok(None) means the reader ended on a frame boundary.
+    pub fn read_frame(&mut self) -> io::Result<Option<Protocol>> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(protocol) = self.decoder.next_frame()? {
+                return Ok(Some(protocol));
+            }
+            let len = chunk.len().min(self.decoder.remaining_capacity());
+            match self.reader.read(&mut chunk[..len]) {
+                Ok(0) if self.decoder.buffered_len() == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.decoder.extend(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = io::Result<Protocol>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+/// writes protocol frames to any blocking writer.
+pub struct FrameWriter<W> {
+    writer: W,
+    session: Option<Session>,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            session: None,
+        }
+    }
+    // set the session negotiated by the handshake.
+    pub fn set_session(&mut self, session: Option<Session>) {
+        self.session = session;
+    }
+    // write a whole frame and flush it.
+    pub fn write_frame(&mut self, protocol: &mut Protocol) -> io::Result<()> {
+        protocol.compress_for(self.session.as_ref());
+        self.writer.write_all(&protocol.to_byte_vec())?;
+        self.writer.flush()
+    }
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
@@ -1,15 +1,20 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     sync::{Arc, RwLock},
     thread,
     time::Duration,
 };
 
 use lazy_static::lazy_static;
+use mio::Token;
 
 use crate::{
-    channel::{Channel, ChannelMode},
+    channel::{Channel, ChannelConfig, ChannelMode, dead_letter},
+    error::{ErrorCode, LuminMQError},
+    id::MessageId,
     msg::Message,
+    timer::Scheduler,
+    tool::time::now_millis,
 };
 
 lazy_static! {
@@ -55,15 +60,125 @@ impl Groups {
             .unwrap()
             .start();
     }
-    // insert message
-    pub fn insert_message(group_id: String, topic: String, message: Message) {
-        if Groups::contains_topic(group_id.clone(), topic.clone()) {
-            let group = Groups::get_group_by_id(group_id.clone()).unwrap();
-            group
-                .write()
-                .unwrap()
-                .insert_message(topic.clone(), message);
+    // insert message, returns the message id assigned by the broker.
+    // a duplicate send of an idempotent producer is dropped and returns the id of the first one.
+    pub fn insert_message(
+        group_id: String,
+        topic: String,
+        mut message: Message,
+    ) -> Result<u64, LuminMQError> {
+        let channel = Groups::get_channel(group_id, topic)?;
+        let mut channel = channel.write().unwrap();
+        message.id = MessageId::next();
+        if let Some(id) = channel.deduplicate(&message) {
+            return Ok(id);
+        }
+        let id = message.id;
+        if message.is_scheduled(now_millis()) {
+            Scheduler::schedule(message);
+        } else {
+            channel.enqueue(message);
+        }
+        Ok(id)
+    }
+    // insert several messages into one channel atomically, keeping their order.
+    // returns the message ids assigned by the broker in the same order, duplicates are dropped.
+    pub fn insert_messages(
+        group_id: String,
+        topic: String,
+        messages: Vec<Message>,
+    ) -> Result<Vec<u64>, LuminMQError> {
+        let channel = Groups::get_channel(group_id, topic)?;
+        Ok(Groups::enqueue(&mut channel.write().unwrap(), messages))
+    }
+    // enqueue messages into a channel atomically, keeping their order.
+    // returns the message ids assigned by the broker in the same order.
+    fn enqueue(channel: &mut Channel, mut messages: Vec<Message>) -> Vec<u64> {
+        let mut ids = Vec::with_capacity(messages.len());
+        messages.retain_mut(|message| {
+            message.id = MessageId::next();
+            match channel.deduplicate(message) {
+                Some(id) => {
+                    ids.push(id);
+                    false
+                }
+                None => {
+                    ids.push(message.id);
+                    true
+                }
+            }
+        });
+        // scheduled messages wait in the timer, the others keep their order in the channel
+        let now = now_millis();
+        let (scheduled, ready): (Vec<Message>, Vec<Message>) = messages
+            .into_iter()
+            .partition(|message| message.is_scheduled(now));
+        scheduled.into_iter().for_each(Scheduler::schedule);
+        channel.enqueue_batch(ready);
+        ids
+    }
+    // insert the messages of a transaction, they may be sent to several channels.
+    // either every message is inserted or none if a channel does not exist.
+    // returns the message ids assigned by the broker in the same order, duplicates are dropped.
+    pub fn insert_transaction(messages: Vec<Message>) -> Result<Vec<u64>, LuminMQError> {
+        // messages of each channel in order with their position in the transaction,
+        // the channels are sorted by (group, topic) so they are always locked in the same order
+        let mut batches: BTreeMap<(String, String), Vec<(usize, Message)>> = BTreeMap::new();
+        let len = messages.len();
+        for (i, message) in messages.into_iter().enumerate() {
+            batches
+                .entry((message.group_id.clone(), message.topic.name.clone()))
+                .or_default()
+                .push((i, message));
+        }
+        let channels = batches
+            .keys()
+            .map(|(group_id, topic)| Groups::get_channel(group_id.clone(), topic.clone()))
+            .collect::<Result<Vec<Arc<RwLock<Channel>>>, LuminMQError>>()?;
+        // consumers see every message of the transaction or none of them
+        let mut guards: Vec<_> = channels
+            .iter()
+            .map(|channel| channel.write().unwrap())
+            .collect();
+        let mut ids = vec![0; len];
+        for (channel, batch) in guards.iter_mut().zip(batches.into_values()) {
+            let (positions, messages): (Vec<usize>, Vec<Message>) = batch.into_iter().unzip();
+            for (i, id) in positions
+                .into_iter()
+                .zip(Groups::enqueue(channel, messages))
+            {
+                ids[i] = id;
+            }
+        }
+        Ok(ids)
+    }
+    // get the channel of a topic, fails if the group or the topic does not exist.
+    pub fn get_channel(
+        group_id: String,
+        topic: String,
+    ) -> Result<Arc<RwLock<Channel>>, LuminMQError> {
+        let group = Groups::get_group_by_id(group_id.clone()).ok_or_else(|| {
+            LuminMQError::new(
+                ErrorCode::UnknownGroup,
+                format!("Group {} does not exist.", group_id),
+            )
+        })?;
+        let channel = group.read().unwrap().get_channel(topic.clone());
+        channel.ok_or_else(|| {
+            LuminMQError::new(
+                ErrorCode::UnknownTopic,
+                format!("Topic {} does not exist in group {}.", topic, group_id),
+            )
+        })
+    }
+    // redeliver every message in flight to a closed connection.
+    pub fn release(token: Token) {
+        let groups: Vec<Arc<RwLock<Group>>> = GROUPS.read().unwrap().values().cloned().collect();
+        let mut dead_letters = Vec::new();
+        for group in groups {
+            dead_letters.extend(group.read().unwrap().release(token));
         }
+        dead_letter(dead_letters);
     }
     pub fn group_num() -> u64 {
         GROUPS.write().unwrap().len().try_into().unwrap()
@@ -85,6 +200,13 @@ impl Groups {
             0
         }
     }
+    // number of messages of a topic that expired before they were consumed.
+    pub fn channel_expired_num(group_id: String, topic: String) -> u64 {
+        match Groups::get_channel(group_id, topic) {
+            Ok(channel) => channel.read().unwrap().expired_num(),
+            Err(_) => 0,
+        }
+    }
     pub fn get_message_num_by_topic(group_id: String, topic: String) -> u64 {
         if Groups::contains_id(group_id.clone()) {
             let group = Groups::get_group_by_id(group_id);
@@ -130,58 +252,65 @@ impl Groups {
         }
     }
     pub fn insert_channel(group_id: String, topic: String, channel_mode: ChannelMode) {
+        // the default config disables dead-lettering and is always valid
+        let _ = Groups::insert_channel_with_config(
+            group_id,
+            topic,
+            channel_mode,
+            ChannelConfig::default(),
+        );
+    }
+    // insert a channel with its configuration, the dead-letter and expiry channels are created
+    // in pull mode if they are enabled and do not exist yet.
+    pub fn insert_channel_with_config(
+        group_id: String,
+        topic: String,
+        channel_mode: ChannelMode,
+        config: ChannelConfig,
+    ) -> Result<(), LuminMQError> {
+        // a channel dead-lettering into itself would redeliver forever
+        if config.dead_letter_topic(&topic).as_ref() == Some(&topic) {
+            return Err(LuminMQError::new(
+                ErrorCode::InvalidConfig,
+                "The dead-letter topic must differ from the topic.",
+            ));
+        }
         if Groups::contains_id(group_id.clone()) {
             let group = Arc::clone(&Groups::get_mut_group_by_id(group_id.clone()).unwrap());
-            if !group.read().unwrap().contains_channel(topic.clone()) {
-                group
-                    .write()
-                    .unwrap()
-                    .insert_channel(topic.clone(), channel_mode);
+            let group = group.write().unwrap();
+            if let Some(dead_letter_topic) = config.dead_letter_topic(&topic)
+                && !group.contains_channel(dead_letter_topic.clone())
+            {
+                group.insert_channel(dead_letter_topic, ChannelMode::Pull);
+            }
+            if let Some(expiry_topic) = config.expiry_topic.clone()
+                && expiry_topic != topic
+                && !group.contains_channel(expiry_topic.clone())
+            {
+                group.insert_channel(expiry_topic, ChannelMode::Pull);
+            }
+            if !group.contains_channel(topic.clone()) {
+                group.insert_channel_with_config(topic.clone(), channel_mode, config);
             }
         }
+        Ok(())
     }
     pub fn get_channel_mode(grou_id: String, topic: String) -> ChannelMode {
-        if Groups::contains_id(grou_id.clone()) {
-            let group = Groups::get_group_by_id(grou_id);
-            let channel_mode = group
-                .unwrap()
-                .read()
-                .unwrap()
-                .get_channel(topic)
-                .unwrap()
-                .read()
-                .unwrap()
-                .mode;
-            channel_mode
-        } else {
-            ChannelMode::None
+        match Groups::get_channel(grou_id, topic) {
+            Ok(channel) => channel.read().unwrap().mode,
+            Err(_) => ChannelMode::None,
         }
     }
     // get a message from the channel.
-    pub fn get_a_message(grou_id: String, topic: String) -> Result<Message, ()> {
-        if Groups::contains_id(grou_id.clone()) {
-            let group = Arc::clone(&Groups::get_group_by_id(grou_id).unwrap());
-            if group.read().unwrap().contains_channel(topic.clone()) {
-                match group
-                    .write()
-                    .unwrap()
-                    .get_channel(topic.clone())
-                    .unwrap()
-                    .write()
-                    .unwrap()
-                    .dequeue()
-                {
-                    Some(msg) => {
-                        return Ok(msg);
-                    }
-                    None => Err(()),
-                }
-            } else {
-                Err(())
-            }
-        } else {
-            Err(())
-        }
+    pub fn get_a_message(grou_id: String, topic: String) -> Result<Message, LuminMQError> {
+        let channel = Groups::get_channel(grou_id, topic.clone())?;
+        let message = channel.write().unwrap().dequeue();
+        message.ok_or_else(|| {
+            LuminMQError::new(
+                ErrorCode::QueueEmpty,
+                format!("No message exists in topic {}.", topic),
+            )
+        })
     }
 }
 
@@ -205,13 +334,22 @@ impl Group {
         }
     }
     pub fn insert_channel(&self, topic: String, mode: ChannelMode) {
+        self.insert_channel_with_config(topic, mode, ChannelConfig::default());
+    }
+    pub fn insert_channel_with_config(
+        &self,
+        topic: String,
+        mode: ChannelMode,
+        config: ChannelConfig,
+    ) {
         self.channels.write().unwrap().insert(
             topic.clone(),
-            Arc::new(RwLock::new(Channel::new(
+            Arc::new(RwLock::new(Channel::with_config(
                 topic.to_string(),
                 self.id.clone(),
                 mode,
                 self.mode.clone(),
+                config,
             ))),
         );
         self.get_channel(topic.clone())
@@ -235,13 +373,16 @@ impl Group {
     pub fn contains_channel(&self, topic: String) -> bool {
         self.channels.write().unwrap().contains_key(&topic.clone())
     }
-    pub fn insert_message(&mut self, topic: String, message: Message) {
-        match self.get_channel(topic.clone()) {
-            Some(channel) => {
-                channel.write().unwrap().enqueue(message);
-            }
-            None => (),
+    // redeliver every message of the group in flight to a closed connection.
+    // returns the messages that reached the max delivery attempts.
+    pub fn release(&self, token: Token) -> Vec<Message> {
+        let channels: Vec<Arc<RwLock<Channel>>> =
+            self.channels.read().unwrap().values().cloned().collect();
+        let mut dead_letters = Vec::new();
+        for channel in channels {
+            dead_letters.extend(channel.read().unwrap().release(token));
         }
+        dead_letters
     }
     pub fn start(&self) {
         let _id = self.id.clone();
@@ -0,0 +1,102 @@
+/// connect-time handshake, negotiates the protocol version and optional capabilities.
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{ErrorCode, LuminMQError},
+    protocol::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION},
+};
+
+/// optional protocol capabilities, a set of bit flags.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    // no optional capability
+    pub const NONE: Capabilities = Capabilities(0);
+    // frame body compression
+    pub const COMPRESSION: Capabilities = Capabilities(1);
+    // multiple messages in one frame
+    pub const BATCHING: Capabilities = Capabilities(1 << 1);
+    // consumer acknowledgements
+    pub const ACKS: Capabilities = Capabilities(1 << 2);
+    // transactional publish
+    pub const TRANSACTIONS: Capabilities = Capabilities(1 << 3);
+    // every capability supported by this build
+    pub const ALL: Capabilities = Capabilities(1 | 1 << 1 | 1 << 2 | 1 << 3);
+
+    pub fn contains(&self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn insert(&mut self, other: Capabilities) {
+        self.0 |= other.0;
+    }
+    pub fn remove(&mut self, other: Capabilities) {
+        self.0 &= !other.0;
+    }
+    // capabilities supported by both sides
+    pub fn intersect(&self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+}
+
+/// hello message exchanged when a connection is established.
+/// the client announces the version range and capabilities it supports,
+/// the server answers with the negotiated version and capabilities.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    // highest supported protocol version
+    pub version: u16,
+    // lowest supported protocol version
+    pub min_version: u16,
+    pub capabilities: Capabilities,
+}
+
+impl Hello {
+    pub fn new(version: u16, min_version: u16, capabilities: Capabilities) -> Self {
+        Self {
+            version,
+            min_version,
+            capabilities,
+        }
+    }
+    pub fn to_data(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+    pub fn from_data(data: &[u8]) -> Result<Self, LuminMQError> {
+        serde_json::from_slice(data).map_err(|e| {
+            LuminMQError::new(
+                ErrorCode::InvalidMessage,
+                format!("Invalid hello message: {}", e),
+            )
+        })
+    }
+    // negotiate with the hello of the peer, the highest version supported by both sides
+    // and the common capabilities are selected.
+    pub fn negotiate(&self, peer: &Hello) -> Result<Hello, LuminMQError> {
+        let version = self.version.min(peer.version);
+        if version < self.min_version || version < peer.min_version {
+            return Err(LuminMQError::new(
+                ErrorCode::UnsupportedVersion,
+                format!(
+                    "Unsupported protocol version {}..={}, supported versions are {}..={}",
+                    peer.min_version, peer.version, self.min_version, self.version
+                ),
+            ));
+        }
+        Ok(Hello::new(
+            version,
+            version,
+            self.capabilities.intersect(peer.capabilities),
+        ))
+    }
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            capabilities: Capabilities::ALL,
+        }
+    }
+}
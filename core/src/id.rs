@@ -0,0 +1,89 @@
+/// unique message ids assigned by the broker.
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use lazy_static::lazy_static;
+
+// 2025-01-01T00:00:00Z in milliseconds, the timestamp of an id is relative to it.
+const EPOCH: u64 = 1_735_689_600_000;
+const NODE_ID_BITS: u64 = 10;
+const SEQUENCE_BITS: u64 = 12;
+// largest node id
+pub const MAX_NODE_ID: u16 = (1 << NODE_ID_BITS) - 1;
+const MAX_SEQUENCE: u16 = (1 << SEQUENCE_BITS) - 1;
+
+lazy_static! {
+    // id generator of this broker.
+    static ref MESSAGE_ID_GENERATOR: Mutex<IdGenerator> = Mutex::new(IdGenerator::new(0));
+}
+
+/// snowflake style id generator, ids are unique per node and roughly time ordered.
+/// layout: 42 bits milliseconds since EPOCH, 10 bits node id, 12 bits sequence.
+pub struct IdGenerator {
+    node_id: u16,
+    // timestamp of the last id
+    last_timestamp: u64,
+    // sequence of the last id within its millisecond
+    sequence: u16,
+}
+
+impl IdGenerator {
+    pub fn new(node_id: u16) -> Self {
+        Self {
+            node_id: node_id & MAX_NODE_ID,
+            last_timestamp: 0,
+            sequence: 0,
+        }
+    }
+    // change the node id, ids assigned before stay lower than the next ones.
+    pub fn set_node_id(&mut self, node_id: u16) {
+        self.node_id = node_id & MAX_NODE_ID;
+        // the node id is above the sequence, the next id must not share a millisecond
+        // with the ids of the old node id
+        self.sequence = MAX_SEQUENCE;
+    }
+    pub fn next_id(&mut self) -> u64 {
+        // never go back in time, even if the system clock does
+        let mut timestamp = now().max(self.last_timestamp);
+        if timestamp == self.last_timestamp {
+            self.sequence = (self.sequence + 1) & MAX_SEQUENCE;
+            if self.sequence == 0 {
+                // the sequence of this millisecond is exhausted, borrow the next one
+                timestamp += 1;
+            }
+        } else {
+            self.sequence = 0;
+        }
+        self.last_timestamp = timestamp;
+        (timestamp - EPOCH) << (NODE_ID_BITS + SEQUENCE_BITS)
+            | (self.node_id as u64) << SEQUENCE_BITS
+            | self.sequence as u64
+    }
+}
+
+pub struct MessageId;
+impl MessageId {
+    // the next unique message id of this broker.
+    pub fn next() -> u64 {
+        MESSAGE_ID_GENERATOR.lock().unwrap().next_id()
+    }
+    // node id of this broker, brokers sharing consumers must use different node ids.
+    pub fn set_node_id(node_id: u16) {
+        MESSAGE_ID_GENERATOR.lock().unwrap().set_node_id(node_id);
+    }
+    // milliseconds since the unix epoch at which the id was generated.
+    pub fn timestamp(id: u64) -> u64 {
+        (id >> (NODE_ID_BITS + SEQUENCE_BITS)) + EPOCH
+    }
+}
+
+// milliseconds since the unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(EPOCH)
+        .max(EPOCH)
+}
@@ -1,7 +1,15 @@
 pub mod channel;
+pub mod compression;
+pub mod connection;
+pub mod error;
+pub mod framed;
 pub mod group;
+pub mod handshake;
+pub mod id;
 pub mod msg;
 pub mod protocol;
+pub mod session;
+pub mod timer;
 pub mod tool;
 pub mod topic;
 pub mod types;
@@ -1,14 +1,29 @@
-use std::io::Write;
+use std::{
+    borrow::Cow,
+    io,
+    str::Utf8Error,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use bincode::{Decode, Encode, error::DecodeError};
-use mio::net::TcpStream;
+use mio::Token;
 
 use crate::{
-    channel::ChannelMode,
+    channel::{ChannelMode, RESERVED_HEADER_PREFIX, dead_letter},
+    connection::Connection,
+    error::{ErrorCode, LuminMQError},
     group::Groups,
+    handshake::{Capabilities, Hello},
     protocol::Protocol,
-    tool::codec::{decode, encode},
+    session::Session,
+    tool::{
+        codec::{decode, encode},
+        time::now_millis,
+    },
     topic::Topic,
+    types::{
+        ConnectionPoolAndGroupBind, ConnectionSession, ConnectionTransaction, MAX_TRANSACTION_SIZE,
+    },
 };
 
 /// message type
@@ -22,6 +37,63 @@ pub enum MessageType {
     None,
 }
 
+/// system action, identifies what a system message asks for.
+#[derive(Encode, Decode, PartialEq, Debug, Clone, Copy)]
+pub enum SystemAction {
+    // none
+    None,
+    // the client announces the protocol versions and capabilities it supports.
+    ClientHello,
+    // the server answers with the negotiated protocol version and capabilities.
+    ServerHello,
+    // heartbeat, either side asks the peer whether it is alive.
+    Ping,
+    // heartbeat answer.
+    Pong,
+    // the client consumes the channel of the message group and topic.
+    Subscribe,
+    // the client no longer consumes the channel of the message group and topic.
+    Unsubscribe,
+    // publisher confirm, the sent message was enqueued. carries the message id.
+    // a rejected message is answered with an error frame instead.
+    PublishAck,
+    // the consumer processed the pushed message with the message id.
+    Ack,
+    // the consumer failed to process the pushed message with the message id, it is redelivered.
+    // the data carries the reason as utf-8 text.
+    Nack,
+    // the client opens a transaction, the messages it sends are staged until the commit.
+    Begin,
+    // the client commits its transaction, the staged messages are enqueued.
+    // the answer carries the message ids.
+    Commit,
+    // the client discards its transaction and the staged messages.
+    Abort,
+}
+
+impl SystemAction {
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1 => SystemAction::ClientHello,
+            2 => SystemAction::ServerHello,
+            3 => SystemAction::Ping,
+            4 => SystemAction::Pong,
+            5 => SystemAction::Subscribe,
+            6 => SystemAction::Unsubscribe,
+            7 => SystemAction::PublishAck,
+            8 => SystemAction::Ack,
+            9 => SystemAction::Nack,
+            10 => SystemAction::Begin,
+            11 => SystemAction::Commit,
+            12 => SystemAction::Abort,
+            _ => SystemAction::None,
+        }
+    }
+}
+
 /// message type
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub enum MessageStatus {
@@ -45,6 +117,55 @@ pub enum ConsumerType {
     None,
 }
 
+/// user-defined message headers, an ordered key/value map.
+/// keys keep their insertion order, inserting an existing key replaces its value in place.
+#[derive(Encode, Decode, PartialEq, Debug, Clone, Default)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+    // insert a header, returns the previous value of the key.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        let key = key.into();
+        let value = value.into();
+        match self.0.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => Some(std::mem::replace(v, value)),
+            None => {
+                self.0.push((key, value));
+                None
+            }
+        }
+    }
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.remove(index).1)
+    }
+    // keep only the headers for which the predicate returns true.
+    pub fn retain(&mut self, mut f: impl FnMut(&str, &str) -> bool) {
+        self.0.retain(|(k, v)| f(k, v));
+    }
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
 /// Message struct used only for data transmission and serialization.
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub struct MessageDTO {
@@ -65,8 +186,33 @@ pub struct MessageDTO {
     // 1: fail
     // 2: none
     status: u16,
-    // business data. If msg_type is 0, this field may be empty.
-    data: String,
+    // business data, raw bytes. If msg_type is 0, this field may be empty.
+    data: Vec<u8>,
+    // optional content type / encoding of the business data, e.g. "application/x-protobuf".
+    content_type: Option<String>,
+    // user-defined headers, e.g. trace id, tenant id or schema version.
+    headers: Headers,
+    // system action, only meaningful if msg_type is 0.
+    action: u16,
+    // unique message id assigned by the broker on enqueue, 0 if not assigned yet.
+    id: u64,
+    // number of times the message was pushed to a consumer that negotiated acks.
+    delivery_attempts: u32,
+    // milliseconds since the unix epoch before which the message is not delivered,
+    // 0 delivers immediately.
+    deliver_at: u64,
+    // time to live in milliseconds, counted from the enqueue. 0 uses the default of the topic.
+    ttl: u64,
+    // milliseconds since the unix epoch after which the message is discarded, 0 never expires.
+    expires_at: u64,
+    // higher priorities overtake lower ones in channels with priority levels.
+    priority: u8,
+    // ordering key, messages with the same key are consumed in order by one consumer of a cluster.
+    key: Option<String>,
+    // idempotent producer id, None if the producer does not deduplicate.
+    producer_id: Option<String>,
+    // sequence number of the message within its producer.
+    sequence: u64,
 }
 
 impl MessageDTO {
@@ -76,7 +222,7 @@ impl MessageDTO {
         msg_type: u16,
         consumer_type: u16,
         status: u16,
-        data: String,
+        data: Vec<u8>,
     ) -> Self {
         Self {
             group_id: group_id,
@@ -85,6 +231,18 @@ impl MessageDTO {
             consumer_type: consumer_type,
             status: status,
             data: data,
+            content_type: None,
+            headers: Headers::new(),
+            action: SystemAction::None.code(),
+            id: 0,
+            delivery_attempts: 0,
+            deliver_at: 0,
+            ttl: 0,
+            expires_at: 0,
+            priority: 0,
+            key: None,
+            producer_id: None,
+            sequence: 0,
         }
     }
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
@@ -100,6 +258,8 @@ impl MessageDTO {
                 name: self.topic.clone(),
             },
             data: self.data.clone(),
+            content_type: self.content_type.clone(),
+            headers: self.headers.clone(),
             msg_type: if self.msg_type == 0 {
                 MessageType::System
             } else if self.msg_type == 1 {
@@ -121,6 +281,17 @@ impl MessageDTO {
             } else {
                 MessageStatus::None
             },
+            action: SystemAction::from_code(self.action),
+            id: self.id,
+            delivery_attempts: self.delivery_attempts,
+            deliver_at: self.deliver_at,
+            ttl: self.ttl,
+            expires_at: self.expires_at,
+            priority: self.priority,
+            key: self.key.clone(),
+            producer_id: self.producer_id.clone(),
+            sequence: self.sequence,
+            correlation_id: 0,
         }
     }
 }
@@ -133,7 +304,19 @@ impl Default for MessageDTO {
             msg_type: 0,
             consumer_type: 0,
             status: 0,
-            data: "".to_string(),
+            data: Vec::new(),
+            content_type: None,
+            headers: Headers::new(),
+            action: SystemAction::None.code(),
+            id: 0,
+            delivery_attempts: 0,
+            deliver_at: 0,
+            ttl: 0,
+            expires_at: 0,
+            priority: 0,
+            key: None,
+            producer_id: None,
+            sequence: 0,
         }
     }
 }
@@ -142,17 +325,50 @@ impl Default for MessageDTO {
 pub struct Message {
     pub group_id: String,
     pub topic: Topic,
-    pub data: String,
+    // raw business data
+    pub data: Vec<u8>,
+    // optional content type / encoding of the business data
+    pub content_type: Option<String>,
+    // user-defined headers
+    pub headers: Headers,
     pub msg_type: MessageType,
     pub consumer_type: ConsumerType,
     pub status: MessageStatus,
+    pub action: SystemAction,
+    // unique message id assigned by the broker on enqueue, 0 if not assigned yet.
+    pub id: u64,
+    // number of times the message was pushed to a consumer that negotiated acks,
+    // greater than 1 if the message is redelivered.
+    pub delivery_attempts: u32,
+    // milliseconds since the unix epoch before which the message is not delivered,
+    // 0 delivers immediately.
+    pub deliver_at: u64,
+    // time to live in milliseconds, counted from the enqueue. 0 uses the default of the topic.
+    pub ttl: u64,
+    // milliseconds since the unix epoch after which the message is discarded, 0 never expires.
+    // set by the broker on enqueue.
+    pub expires_at: u64,
+    // higher priorities overtake lower ones in channels with priority levels,
+    // priorities above the highest level of the channel use the highest level.
+    pub priority: u8,
+    // ordering key, in a cluster group messages with the same key are pushed to the same
+    // consumer and, if it negotiated acks, one at a time.
+    pub key: Option<String>,
+    // idempotent producer id, the broker applies a message of a producer and sequence once.
+    // None if the producer does not deduplicate.
+    pub producer_id: Option<String>,
+    // sequence number of the message within its producer, a retried send keeps it.
+    pub sequence: u64,
+    // correlation id of the frame the message was received in or is sent with,
+    // not part of the message itself.
+    pub correlation_id: u64,
 }
 
 impl Message {
     pub fn new(
         group_id: String,
         topic: String,
-        data: String,
+        data: impl Into<Vec<u8>>,
         msg_type: MessageType,
         consumer_type: ConsumerType,
         status: MessageStatus,
@@ -160,12 +376,100 @@ impl Message {
         Self {
             group_id: group_id,
             topic: Topic { name: topic },
-            data: data,
+            data: data.into(),
+            content_type: None,
+            headers: Headers::new(),
             msg_type: msg_type,
             consumer_type: consumer_type,
             status: status,
+            action: SystemAction::None,
+            id: 0,
+            delivery_attempts: 0,
+            deliver_at: 0,
+            ttl: 0,
+            expires_at: 0,
+            priority: 0,
+            key: None,
+            producer_id: None,
+            sequence: 0,
+            correlation_id: 0,
+        }
+    }
+    // build a system message.
+    pub fn system(action: SystemAction, status: MessageStatus, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            data: data.into(),
+            msg_type: MessageType::System,
+            status,
+            action,
+            ..Message::default()
         }
     }
+    // the business data as utf-8 text.
+    pub fn text(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.data)
+    }
+    // the business data as utf-8 text, invalid sequences are replaced.
+    pub fn text_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.data)
+    }
+    // replace the business data with utf-8 text.
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.data = text.into().into_bytes();
+    }
+    // get a user-defined header.
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key)
+    }
+    // set a user-defined header.
+    pub fn set_header(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.headers.insert(key, value);
+    }
+    // deliver the message once the delay has passed since it was sent.
+    pub fn set_delay(&mut self, delay: Duration) {
+        let delay = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX);
+        self.deliver_at = now_millis().saturating_add(delay);
+    }
+    // deliver the message at a point in time.
+    pub fn set_deliver_at(&mut self, time: SystemTime) {
+        self.deliver_at = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+    }
+    // the message waits for its delivery time.
+    pub fn is_scheduled(&self, now: u64) -> bool {
+        self.deliver_at > now
+    }
+    // discard the message if it is not consumed within the time to live.
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+    }
+    // messages with a higher priority are delivered first.
+    pub fn set_priority(&mut self, priority: u8) {
+        self.priority = priority;
+    }
+    // messages with the same key are consumed in order, e.g. the updates of one order.
+    pub fn set_key(&mut self, key: impl Into<String>) {
+        self.key = Some(key.into());
+    }
+    // make the message idempotent, sends of a producer id and sequence are applied once.
+    pub fn set_producer(&mut self, producer_id: impl Into<String>, sequence: u64) {
+        self.producer_id = Some(producer_id.into());
+        self.sequence = sequence;
+    }
+    // the time to live has passed.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at != 0 && self.expires_at <= now
+    }
+    // clear the fields owned by the broker on a message received from a producer.
+    fn received(&mut self) {
+        self.correlation_id = 0;
+        self.delivery_attempts = 0;
+        self.expires_at = 0;
+        self.headers
+            .retain(|key, _| !key.starts_with(RESERVED_HEADER_PREFIX));
+    }
     pub fn is_group_id_empty(&self) -> bool {
         self.group_id.is_empty()
     }
@@ -173,7 +477,7 @@ impl Message {
         self.topic.is_name_empty()
     }
     pub fn to_messagedto(&self) -> MessageDTO {
-        MessageDTO::new(
+        let mut dto = MessageDTO::new(
             self.group_id.to_string(),
             self.topic.name.to_string(),
             match self.msg_type {
@@ -191,66 +495,396 @@ impl Message {
                 MessageStatus::Fail => 1,
                 MessageStatus::None => 2,
             },
-            self.data.to_string(),
-        )
+            self.data.clone(),
+        );
+        dto.content_type = self.content_type.clone();
+        dto.headers = self.headers.clone();
+        dto.action = self.action.code();
+        dto.id = self.id;
+        dto.delivery_attempts = self.delivery_attempts;
+        dto.deliver_at = self.deliver_at;
+        dto.ttl = self.ttl;
+        dto.expires_at = self.expires_at;
+        dto.priority = self.priority;
+        dto.key = self.key.clone();
+        dto.producer_id = self.producer_id.clone();
+        dto.sequence = self.sequence;
+        dto
     }
-    /// message handle
-    pub fn handle(&mut self, stream: &TcpStream) {
+    /// message handle, an error asks the caller to close the connection.
+    pub fn handle(&mut self, token: Token, connection: &Connection) -> io::Result<()> {
         match self.msg_type {
-            MessageType::System => {
-                // system message
-                todo!()
+            MessageType::System => match self.action {
+                SystemAction::ClientHello => return self.handle_client_hello(token, connection),
+                SystemAction::Ping => {
+                    let mut pong = Message::system(SystemAction::Pong, MessageStatus::Success, []);
+                    pong.correlation_id = self.correlation_id;
+                    let _ =
+                        pong.session_writer(connection, ConnectionSession::get(&token).as_ref());
+                }
+                // any frame proves the peer is alive, nothing else to do
+                SystemAction::Pong => (),
+                SystemAction::Subscribe
+                | SystemAction::Unsubscribe
+                | SystemAction::Ack
+                | SystemAction::Nack
+                | SystemAction::Begin
+                | SystemAction::Commit
+                | SystemAction::Abort
+                    if !ConnectionSession::is_established(&token) =>
+                {
+                    return Err(self.reject_unestablished(connection));
+                }
+                SystemAction::Subscribe => self.reply(token, connection, self.subscribe(token)),
+                SystemAction::Unsubscribe => self.reply(token, connection, self.unsubscribe(token)),
+                SystemAction::Ack | SystemAction::Nack => {
+                    let result = self.acknowledge(token);
+                    // acks are fire and forget unless the consumer asks for an answer
+                    if self.correlation_id != 0 {
+                        self.reply(token, connection, result);
+                    }
+                }
+                SystemAction::Begin => self.reply(token, connection, self.begin(token)),
+                SystemAction::Commit => self.reply_with(token, connection, self.commit(token)),
+                SystemAction::Abort => self.reply(token, connection, self.abort(token)),
+                SystemAction::ServerHello => (),
+                SystemAction::PublishAck => (),
+                SystemAction::None => (),
+            },
+            MessageType::Business if !ConnectionSession::is_established(&token) => {
+                return Err(self.reject_unestablished(connection));
             }
             MessageType::Business => match self.consumer_type {
-                ConsumerType::Pull => {
-                    if ChannelMode::Pull.eq(&Groups::get_channel_mode(
-                        self.group_id.clone(),
-                        self.topic.name.clone(),
-                    )) {
-                        // handle ..
-                        match Groups::get_a_message(
-                            self.group_id.to_string(),
-                            self.topic.name.to_string(),
-                        ) {
-                            Ok(mut msg) => {
-                                msg.status = MessageStatus::Success;
-                                let _ = msg.writer(stream);
-                            }
-                            Err(_) => {
-                                self.status = MessageStatus::Fail;
-                                self.data = "No message exists.".to_string();
-                                let _ = self.writer(stream);
-                            }
-                        }
+                ConsumerType::Pull => match self.pull() {
+                    Ok(mut msg) => {
+                        msg.status = MessageStatus::Success;
+                        msg.correlation_id = self.correlation_id;
+                        let _ =
+                            msg.session_writer(connection, ConnectionSession::get(&token).as_ref());
                     }
-                }
+                    Err(e) => {
+                        let _ = e.writer(connection, self.correlation_id);
+                    }
+                },
                 ConsumerType::Send => {
                     // the consumer inserts a new message.
-                    Groups::insert_message(
-                        self.group_id.clone(),
-                        self.topic.name.clone(),
-                        self.clone(),
-                    );
+                    let mut message = self.clone();
+                    message.received();
+                    let result = if ConnectionTransaction::is_open(&token) {
+                        Message::stage(token, vec![message]).map(|_| 0)
+                    } else {
+                        Groups::insert_message(
+                            self.group_id.clone(),
+                            self.topic.name.clone(),
+                            message,
+                        )
+                    };
+                    match result {
+                        // a correlated send is confirmed with the assigned message id,
+                        // a failed one is answered with an error frame.
+                        // a staged message is confirmed with id 0, commit assigns the ids
+                        Ok(id) if self.correlation_id != 0 => {
+                            let _ = self.publish_ack(id).session_writer(
+                                connection,
+                                ConnectionSession::get(&token).as_ref(),
+                            );
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            let _ = e.writer(connection, self.correlation_id);
+                        }
+                    }
                 }
                 ConsumerType::None => {}
             },
             MessageType::None => (),
         }
+        Ok(())
+    }
+    // get a message from the channel, only channels in pull mode can be pulled from.
+    fn pull(&self) -> Result<Message, LuminMQError> {
+        let mode = Groups::get_channel(self.group_id.clone(), self.topic.name.clone())?
+            .read()
+            .unwrap()
+            .mode;
+        if mode != ChannelMode::Pull {
+            return Err(LuminMQError::new(
+                ErrorCode::WrongChannelMode,
+                format!("Topic {} is not in pull mode.", self.topic.name),
+            ));
+        }
+        Groups::get_a_message(self.group_id.clone(), self.topic.name.clone())
+    }
+    // bind the connection to the channel it consumes, only channels in push mode can be subscribed to.
+    fn subscribe(&self, token: Token) -> Result<(), LuminMQError> {
+        let mode = Groups::get_channel(self.group_id.clone(), self.topic.name.clone())?
+            .read()
+            .unwrap()
+            .mode;
+        if mode != ChannelMode::Push {
+            return Err(LuminMQError::new(
+                ErrorCode::WrongChannelMode,
+                format!("Topic {} is not in push mode.", self.topic.name),
+            ));
+        }
+        ConnectionPoolAndGroupBind::insert(token, (self.group_id.clone(), self.topic.name.clone()));
+        Ok(())
+    }
+    // unbind the connection from the channel, pushes to the connection stop.
+    fn unsubscribe(&self, token: Token) -> Result<(), LuminMQError> {
+        Groups::get_channel(self.group_id.clone(), self.topic.name.clone())?;
+        ConnectionPoolAndGroupBind::unbind(
+            &token,
+            &(self.group_id.clone(), self.topic.name.clone()),
+        );
+        Ok(())
+    }
+    // settle a message in flight to the connection, a nack redelivers it.
+    // settling a message that is no longer in flight, e.g. after its ack timed out, has no effect.
+    fn acknowledge(&self, token: Token) -> Result<(), LuminMQError> {
+        let acks = ConnectionSession::get(&token)
+            .is_some_and(|session| session.supports(Capabilities::ACKS));
+        if !acks {
+            return Err(LuminMQError::new(
+                ErrorCode::CapabilityNotNegotiated,
+                "Acks were not negotiated.",
+            ));
+        }
+        let channel = Groups::get_channel(self.group_id.clone(), self.topic.name.clone())?;
+        let dead_letters = {
+            let channel = channel.read().unwrap();
+            if self.action == SystemAction::Ack {
+                channel.ack(self.id, token);
+                Vec::new()
+            } else {
+                channel.nack(self.id, token, &self.text_lossy())
+            }
+        };
+        // the channel guard is dropped, the dead-letter channel may be this channel's peer
+        dead_letter(dead_letters);
+        Ok(())
+    }
+    // open a transaction on the connection.
+    fn begin(&self, token: Token) -> Result<(), LuminMQError> {
+        Message::transactions_negotiated(token)?;
+        if !ConnectionTransaction::begin(token) {
+            return Err(LuminMQError::new(
+                ErrorCode::InvalidTransaction,
+                "A transaction is already open.",
+            ));
+        }
+        Ok(())
+    }
+    // enqueue the staged messages, the answer carries their ids encoded as Vec<u64>.
+    // if a channel no longer exists nothing is enqueued and the transaction is closed.
+    fn commit(&self, token: Token) -> Result<Vec<u8>, LuminMQError> {
+        Message::transactions_negotiated(token)?;
+        let messages = ConnectionTransaction::remove(&token).ok_or_else(|| {
+            LuminMQError::new(ErrorCode::InvalidTransaction, "No transaction is open.")
+        })?;
+        Groups::insert_transaction(messages).map(encode)
+    }
+    // discard the staged messages.
+    fn abort(&self, token: Token) -> Result<(), LuminMQError> {
+        Message::transactions_negotiated(token)?;
+        match ConnectionTransaction::remove(&token) {
+            Some(_) => Ok(()),
+            None => Err(LuminMQError::new(
+                ErrorCode::InvalidTransaction,
+                "No transaction is open.",
+            )),
+        }
+    }
+    // stage messages in the open transaction of the connection, their channels must exist.
+    fn stage(token: Token, messages: Vec<Message>) -> Result<(), LuminMQError> {
+        for message in messages.iter() {
+            Groups::get_channel(message.group_id.clone(), message.topic.name.clone())?;
+        }
+        if !ConnectionTransaction::stage(&token, messages) {
+            return Err(LuminMQError::new(
+                ErrorCode::InvalidTransaction,
+                format!(
+                    "A transaction may stage at most {} messages.",
+                    MAX_TRANSACTION_SIZE
+                ),
+            ));
+        }
+        Ok(())
+    }
+    fn transactions_negotiated(token: Token) -> Result<(), LuminMQError> {
+        let transactions = ConnectionSession::get(&token)
+            .is_some_and(|session| session.supports(Capabilities::TRANSACTIONS));
+        if !transactions {
+            return Err(LuminMQError::new(
+                ErrorCode::CapabilityNotNegotiated,
+                "Transactions were not negotiated.",
+            ));
+        }
+        Ok(())
+    }
+    // answer a system request with a success message or an error frame.
+    fn reply(&self, token: Token, connection: &Connection, result: Result<(), LuminMQError>) {
+        self.reply_with(token, connection, result.map(|_| Vec::new()));
+    }
+    // answer a system request with a success message carrying data or an error frame.
+    fn reply_with(
+        &self,
+        token: Token,
+        connection: &Connection,
+        result: Result<Vec<u8>, LuminMQError>,
+    ) {
+        match result {
+            Ok(data) => {
+                let mut reply = Message::system(self.action, MessageStatus::Success, data);
+                reply.group_id = self.group_id.clone();
+                reply.topic = self.topic.clone();
+                reply.correlation_id = self.correlation_id;
+                let _ = reply.session_writer(connection, ConnectionSession::get(&token).as_ref());
+            }
+            Err(e) => {
+                let _ = e.writer(connection, self.correlation_id);
+            }
+        }
+    }
+    /// batch message handle, a batch may only contain messages sent to a single channel,
+    /// they are enqueued atomically and in order.
+    pub fn handle_batch(
+        messages: Vec<Message>,
+        token: Token,
+        connection: &Connection,
+    ) -> io::Result<()> {
+        let first = match messages.first() {
+            Some(first) => first.clone(),
+            None => return Ok(()),
+        };
+        let session = match ConnectionSession::get(&token) {
+            Some(session) => session,
+            None => return Err(first.reject_unestablished(connection)),
+        };
+        let result = if !session.supports(Capabilities::BATCHING) {
+            Err(LuminMQError::new(
+                ErrorCode::CapabilityNotNegotiated,
+                "Batching was not negotiated.",
+            ))
+        } else if messages.iter().any(|m| {
+            m.msg_type != MessageType::Business
+                || m.consumer_type != ConsumerType::Send
+                || m.group_id != first.group_id
+                || m.topic != first.topic
+        }) {
+            Err(LuminMQError::new(
+                ErrorCode::InvalidMessage,
+                "A batch may only contain messages sent to the same channel.",
+            ))
+        } else {
+            let messages: Vec<Message> = messages
+                .into_iter()
+                .map(|mut message| {
+                    message.received();
+                    message
+                })
+                .collect();
+            if ConnectionTransaction::is_open(&token) {
+                let len = messages.len();
+                Message::stage(token, messages).map(|_| vec![0; len])
+            } else {
+                Groups::insert_messages(first.group_id.clone(), first.topic.name.clone(), messages)
+            }
+        };
+        match result {
+            // a correlated batch is confirmed with the assigned message ids, in order
+            Ok(ids) if first.correlation_id != 0 => {
+                let acks: Vec<Message> = ids.into_iter().map(|id| first.publish_ack(id)).collect();
+                let _ = Message::batch_writer(&acks, connection, Some(&session));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let _ = e.writer(connection, first.correlation_id);
+            }
+        }
+        Ok(())
+    }
+    // publisher confirm of a sent message, carries the message id assigned by the broker.
+    fn publish_ack(&self, id: u64) -> Message {
+        Message {
+            group_id: self.group_id.clone(),
+            topic: self.topic.clone(),
+            msg_type: MessageType::System,
+            status: MessageStatus::Success,
+            action: SystemAction::PublishAck,
+            id,
+            correlation_id: self.correlation_id,
+            ..Message::default()
+        }
+    }
+    // reply to a connection that sends messages before completing the handshake.
+    fn reject_unestablished(&self, connection: &Connection) -> io::Error {
+        let e = LuminMQError::new(
+            ErrorCode::HandshakeRequired,
+            "The handshake must be completed before sending messages.",
+        );
+        let _ = e.writer(connection, self.correlation_id);
+        io::Error::new(io::ErrorKind::PermissionDenied, e)
+    }
+    // negotiate the protocol version and capabilities with a client,
+    // unsupported versions are rejected and the connection is closed.
+    fn handle_client_hello(&self, token: Token, connection: &Connection) -> io::Result<()> {
+        match Hello::from_data(&self.data).and_then(|hello| Hello::default().negotiate(&hello)) {
+            Ok(hello) => {
+                ConnectionSession::insert(token, Session::new(&hello));
+                let mut reply = Message::system(
+                    SystemAction::ServerHello,
+                    MessageStatus::Success,
+                    hello.to_data(),
+                );
+                reply.correlation_id = self.correlation_id;
+                let _ = reply.writer(connection);
+                Ok(())
+            }
+            Err(e) => {
+                let _ = e.writer(connection, self.correlation_id);
+                Err(io::Error::new(io::ErrorKind::Unsupported, e))
+            }
+        }
+    }
+    // write several messages as one batch frame, compressed if the session negotiated compression.
+    // the frame carries the correlation id of the first message.
+    pub fn batch_writer(
+        messages: &[Message],
+        connection: &Connection,
+        session: Option<&Session>,
+    ) -> Result<usize, String> {
+        let protocol = &mut Protocol::default();
+        protocol.head.correlation_id = messages.first().map_or(0, |m| m.correlation_id);
+        protocol.insert_batch(messages.iter().map(|m| m.to_messagedto()).collect());
+        let _ = protocol.ready();
+        protocol.compress_for(session);
+        let protocol_buf = protocol.to_byte_vec();
+        connection
+            .send(&protocol_buf)
+            .map(|_| protocol_buf.len())
+            .map_err(|e| format!("{:?}", e))
     }
     // massge writer
-    pub fn writer(&self, mut stream: &TcpStream) -> Result<usize, String> {
+    pub fn writer(&self, connection: &Connection) -> Result<usize, String> {
+        self.session_writer(connection, None)
+    }
+    // massge writer, the frame body is compressed if the session negotiated compression.
+    pub fn session_writer(
+        &self,
+        connection: &Connection,
+        session: Option<&Session>,
+    ) -> Result<usize, String> {
         let protocol = &mut Protocol::default();
+        protocol.head.correlation_id = self.correlation_id;
         protocol.insert_message(self.to_messagedto());
         let _ = protocol.ready();
+        protocol.compress_for(session);
         let protocol_buf = protocol.to_byte_vec();
-        match stream.write(&protocol_buf) {
-            Ok(size) => {
-                return Ok(size);
-            }
-            Err(e) => {
-                return Err(format!("{:?}", e));
-            }
-        }
+        connection
+            .send(&protocol_buf)
+            .map(|_| protocol_buf.len())
+            .map_err(|e| format!("{:?}", e))
     }
 }
 
@@ -259,10 +893,23 @@ impl Default for Message {
         Self {
             group_id: "".to_string(),
             topic: Topic::default(),
-            data: "".to_string(),
+            data: Vec::new(),
+            content_type: None,
+            headers: Headers::new(),
             msg_type: MessageType::None,
             consumer_type: ConsumerType::None,
             status: MessageStatus::None,
+            action: SystemAction::None,
+            id: 0,
+            delivery_attempts: 0,
+            deliver_at: 0,
+            ttl: 0,
+            expires_at: 0,
+            priority: 0,
+            key: None,
+            producer_id: None,
+            sequence: 0,
+            correlation_id: 0,
         }
     }
 }
@@ -1,18 +1,35 @@
 /// communication protocol
 use bincode::{Decode, Encode, error::DecodeError};
+use lazy_static::lazy_static;
 use mio::net::TcpStream;
-use std::io::{BufRead, BufReader, Write};
+use std::{
+    fmt,
+    io::{self, Read},
+    sync::RwLock,
+};
 use tracing::{Level, event};
 
 use crate::{
+    compression::{CompressionCodec, CompressionOptions},
+    connection::Connection,
+    error::{ErrorCode, ErrorDTO, LuminMQError},
+    handshake::Capabilities,
     msg::{Message, MessageDTO},
-    tool::codec::{decode, encode, serialized_size},
+    session::Session,
+    tool::codec::{decode, decode_with_len, encode, serialized_size},
 };
 
 // fixed protocol identifier
 pub const PROTOCOL_IDENTIFIER: &str = "luminmq";
-// Fixed protocol end identifier
-pub const PROTOCOL_END_IDENTIFIER: &str = "END";
+// current protocol version
+pub const PROTOCOL_VERSION: u16 = 1;
+// lowest protocol version still supported
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+lazy_static! {
+    // limits applied to frames received from peers.
+    static ref FRAME_LIMITS: RwLock<FrameLimits> = RwLock::new(FrameLimits::default());
+}
 
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub struct Protocol {
@@ -57,9 +74,33 @@ impl Protocol {
             }
         }
     }
-    // to byte vec
+    // to byte vec, the body is compressed with the codec flagged in the protocol head
+    // and the end carries the checksum over the encoded head and body.
     pub fn to_byte_vec(&self) -> Vec<u8> {
-        encode(self)
+        let body = self.body.to_byte_vec();
+        let body = match CompressionCodec::from_code(self.head.compression) {
+            Some(CompressionCodec::None) | None => body,
+            Some(codec) => codec.compress(&body, CompressionOptions::get().level),
+        };
+        let mut head = self.head.clone();
+        head.set_data_size(body.len().try_into().unwrap());
+        let mut buf = head.to_byte_vec();
+        buf.extend_from_slice(&body);
+        let end = ProtocolEnd::new(crc32c::crc32c(&buf));
+        buf.extend_from_slice(&encode(&end));
+        buf
+    }
+    // select the body compression for a peer, bodies are only compressed if the peer
+    // negotiated compression and the body reaches the configured threshold.
+    pub fn compress_for(&mut self, session: Option<&Session>) -> &mut Self {
+        let codec = match session {
+            Some(session) if session.supports(Capabilities::COMPRESSION) => {
+                CompressionOptions::get().codec_for(self.body.size())
+            }
+            _ => CompressionCodec::None,
+        };
+        self.head.compression = codec.code();
+        self
     }
     // protocol head size
     pub fn protocol_head_size() -> usize {
@@ -77,62 +118,91 @@ impl Protocol {
         self.end = ProtocolEnd::default();
         Ok(self)
     }
-    // get message
-    pub fn get_message(&self) -> Result<Message, ()> {
-        Ok(self.body.message.to_message())
+    // get message, fails for batch and error frames.
+    // the message carries the correlation id of the frame.
+    pub fn get_message(&self) -> Result<Message, LuminMQError> {
+        match &self.body {
+            ProtocolBody::Message(message) => Ok(self.correlate(message.to_message())),
+            ProtocolBody::Batch(_) => Err(LuminMQError::new(
+                ErrorCode::InvalidMessage,
+                "Expected a single message, received a batch.",
+            )),
+            ProtocolBody::Error(error) => Err(error.to_error()),
+        }
+    }
+    // get all messages carried by the frame, in order.
+    pub fn get_messages(&self) -> Vec<Message> {
+        match &self.body {
+            ProtocolBody::Message(message) => vec![self.correlate(message.to_message())],
+            ProtocolBody::Batch(messages) => messages
+                .iter()
+                .map(|m| self.correlate(m.to_message()))
+                .collect(),
+            ProtocolBody::Error(_) => Vec::new(),
+        }
+    }
+    // get the error carried by an error frame.
+    pub fn get_error(&self) -> Option<LuminMQError> {
+        match &self.body {
+            ProtocolBody::Error(error) => Some(error.to_error()),
+            _ => None,
+        }
+    }
+    // correlation id of the frame, 0 if the frame does not answer a request.
+    pub fn correlation_id(&self) -> u64 {
+        self.head.correlation_id
+    }
+    fn correlate(&self, mut message: Message) -> Message {
+        message.correlation_id = self.head.correlation_id;
+        message
+    }
+    // whether the frame is a batch frame.
+    pub fn is_batch(&self) -> bool {
+        matches!(self.body, ProtocolBody::Batch(_))
     }
     // insert message
     pub fn insert_message(&mut self, message_dto: MessageDTO) {
         self.body.insert_message(message_dto);
     }
+    // insert several messages, turns the frame into a batch frame.
+    pub fn insert_batch(&mut self, message_dtos: Vec<MessageDTO>) {
+        self.body = ProtocolBody::Batch(message_dtos);
+    }
+    // insert an error, turns the frame into an error frame.
+    pub fn insert_error(&mut self, error_dto: ErrorDTO) {
+        self.body = ProtocolBody::Error(error_dto);
+    }
     // protocol handle
-    pub fn handle(stream: &TcpStream, call: impl Fn(Message)) {
-        let mut r: BufReader<&TcpStream> = BufReader::new(stream);
+    // read all available bytes from the stream into the connection decoder and
+    // pass every complete frame to the handler. an error is returned when the peer
+    // closed the connection or the handler asks to close it.
+    // fatal protocol violations are returned as InvalidData errors carrying the ProtocolError,
+    // the connection must not be used for further frames.
+    pub fn handle(
+        stream: &TcpStream,
+        decoder: &mut ProtocolDecoder,
+        mut call: impl FnMut(Protocol) -> io::Result<()>,
+    ) -> io::Result<()> {
         loop {
-            match Protocol::reader(&mut r) {
-                Ok(protocol) => {
-                    // protocol
-                    match protocol.get_message() {
-                        Ok(mut message) => {
-                            call(message.clone());
-                            message.handle(stream);
-                        }
-                        Err(_e) => {}
-                    }
-                }
-                Err(_e) => {
-                    break;
-                }
+            let state = decoder.read_from(stream)?;
+            while let Some(protocol) = decoder.next_frame()? {
+                call(protocol)?;
             }
-        }
-    }
-    // protocol reader
-    pub fn reader(r: &mut BufReader<&TcpStream>) -> Result<Protocol, String> {
-        let mut protocol = Protocol::default();
-        loop {
-            match ProtocolHead::reader(r) {
-                Ok(h) => match ProtocolBody::reader(r, h.data_size.try_into().unwrap()) {
-                    Ok(body) => match ProtocolEnd::reader(r) {
-                        Ok(end) => {
-                            protocol.head = h;
-                            protocol.body = body;
-                            protocol.end = end;
-                            return Ok(protocol);
-                        }
-                        Err(e) => return Err(e),
-                    },
-                    Err(e) => return Err(e),
-                },
-                Err(e) => {
-                    return Err(e);
+            match state {
+                ReadState::Drained => return Ok(()),
+                // the buffered frames were decoded, continue reading
+                ReadState::Full => continue,
+                ReadState::Closed => {
+                    event!(Level::WARN, "Connection is closed.");
+                    return Err(io::ErrorKind::UnexpectedEof.into());
                 }
             }
         }
     }
     // protocol writer
-    pub fn writer(mut stream: &TcpStream, protocol: &mut Protocol) {
+    pub fn writer(connection: &Connection, protocol: &mut Protocol) {
         let protocol_buf = protocol.to_byte_vec();
-        let _ = stream.write(&protocol_buf);
+        let _ = connection.send(&protocol_buf);
     }
 }
 
@@ -156,6 +226,13 @@ impl Default for Protocol {
 pub struct ProtocolHead {
     // fixed-length protocol identifier.
     pub identifier: String,
+    // protocol version the frame is encoded with.
+    pub version: u16,
+    // compression codec of the body, 0 means uncompressed.
+    pub compression: u8,
+    // client-generated request id, echoed by the server in every response.
+    // 0 means the frame is not correlated with a request, e.g. pushed messages.
+    pub correlation_id: u64,
     // the byte size of the data area
     pub data_size: u32,
 }
@@ -196,75 +273,33 @@ impl ProtocolHead {
             Err(_) => return Err("Protocol header serialization exception".to_string()),
         }
     }
-    // protocol head reader
-    pub fn reader(r: &mut BufReader<&TcpStream>) -> Result<ProtocolHead, String> {
-        match r.fill_buf() {
-            Ok(buf) => {
-                let head_size = Protocol::protocol_head_size();
-                let buf_len = buf.len();
-                if buf_len >= head_size {
-                    let head_buf = &buf[..head_size];
-                    if Protocol::verify_protocol_head(&head_buf) {
-                        // build protocol head
-                        match ProtocolHead::build(&head_buf) {
-                            Ok(head) => {
-                                r.consume(head_size);
-                                return Ok(head);
-                            }
-                            Err(e) => {
-                                r.consume(head_size);
-                                return Err(e);
-                            }
-                        }
-                    } else {
-                        r.consume(head_size);
-                        return Err(
-                            "The protocol header does not meet the requirements".to_string()
-                        );
-                    }
-                } else if buf_len < head_size {
-                    r.consume(buf_len);
-                    event!(
-                        Level::WARN,
-                        "Dirty data, the buffer data does not meet the protocol header size."
-                    );
-                    return Err(
-                        "Dirty data, the buffer data does not meet the protocol header size."
-                            .to_string(),
-                    );
-                } else if buf_len == 0 {
-                    event!(Level::WARN, "Connection is closed.");
-                    return Err("Connection is closed.".to_string());
-                } else {
-                    return Err("Other exceptions".to_string());
-                }
-            }
-            Err(_) => {
-                return Err("Not enough data in buffer".to_string());
-            }
-        }
-    }
 }
 
 impl Default for ProtocolHead {
     fn default() -> Self {
         Self {
             identifier: PROTOCOL_IDENTIFIER.to_string(),
+            version: PROTOCOL_VERSION,
+            compression: CompressionCodec::None.code(),
+            correlation_id: 0,
             data_size: 0,
         }
     }
 }
 
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
-pub struct ProtocolBody {
-    pub message: MessageDTO,
+pub enum ProtocolBody {
+    // a single message
+    Message(MessageDTO),
+    // several messages in one frame, handled in order
+    Batch(Vec<MessageDTO>),
+    // a request failed
+    Error(ErrorDTO),
 }
 
 impl ProtocolBody {
     pub fn new(message_dto: MessageDTO) -> Self {
-        Self {
-            message: message_dto,
-        }
+        ProtocolBody::Message(message_dto)
     }
     pub fn size(&self) -> usize {
         serialized_size(self)
@@ -276,7 +311,7 @@ impl ProtocolBody {
         encode(self)
     }
     pub fn insert_message(&mut self, message_dto: MessageDTO) {
-        self.message = message_dto;
+        *self = ProtocolBody::Message(message_dto);
     }
     // build protocol body
     pub fn build(bytes: &[u8]) -> Result<ProtocolBody, String> {
@@ -287,99 +322,337 @@ impl ProtocolBody {
             Err(_e) => return Err("Protocol body serialization exception".to_string()),
         }
     }
-    // protocol head reader
-    pub fn reader(r: &mut BufReader<&TcpStream>, size: usize) -> Result<ProtocolBody, String> {
-        match r.fill_buf() {
-            Ok(buf) => {
-                let buf_len = buf.len();
-                if buf_len >= size {
-                    let body_buf = &buf[..size];
-                    match ProtocolBody::build(body_buf) {
-                        Ok(body) => {
-                            r.consume(size);
-                            return Ok(body);
-                        }
-                        Err(e) => {
-                            r.consume(size);
-                            return Err(e);
-                        }
-                    }
-                } else {
-                    r.consume(buf_len);
-                    return Err("Dirty data.".to_string());
-                }
-            }
-            Err(_) => {
-                return Err("Not enough data in buffer".to_string());
+}
+
+impl Default for ProtocolBody {
+    fn default() -> Self {
+        ProtocolBody::Message(MessageDTO::default())
+    }
+}
+
+/// protocol trailer, carries a crc32c checksum over the encoded head and body.
+#[derive(Encode, Decode, PartialEq, Debug, Clone, Default)]
+pub struct ProtocolEnd {
+    pub checksum: u32,
+}
+
+impl ProtocolEnd {
+    pub fn new(checksum: u32) -> Self {
+        Self { checksum }
+    }
+}
+
+/// protocol violation found while decoding a frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolError {
+    // the bytes do not start with a valid protocol head
+    InvalidHead(String),
+    // the protocol body could not be decompressed or deserialized
+    InvalidBody(String),
+    // the protocol end could not be deserialized
+    InvalidEnd(String),
+    // the checksum in the protocol end does not match the received head and body
+    Corrupted { expected: u32, actual: u32 },
+    // the protocol head announces a body larger than the maximum frame size
+    FrameTooLarge { size: usize, limit: usize },
+    // the read buffer of the connection is full without containing a complete frame
+    BufferOverflow { size: usize, limit: usize },
+}
+
+impl ProtocolError {
+    // a fatal violation leaves no frame boundary to resume from,
+    // the peer is sent an error frame and disconnected.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            ProtocolError::FrameTooLarge { .. } | ProtocolError::BufferOverflow { .. }
+        )
+    }
+    // the error reported to the peer in an error frame.
+    pub fn to_error(&self) -> LuminMQError {
+        let code = match self {
+            ProtocolError::FrameTooLarge { .. } | ProtocolError::BufferOverflow { .. } => {
+                ErrorCode::FrameTooLarge
             }
+            _ => ErrorCode::InvalidMessage,
+        };
+        LuminMQError::new(code, self.to_string())
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::InvalidHead(e) => write!(f, "Invalid protocol head: {}", e),
+            ProtocolError::InvalidBody(e) => write!(f, "Invalid protocol body: {}", e),
+            ProtocolError::InvalidEnd(e) => write!(f, "Invalid protocol end: {}", e),
+            ProtocolError::Corrupted { expected, actual } => write!(
+                f,
+                "Corrupted frame, checksum {:#010x} does not match {:#010x}",
+                actual, expected
+            ),
+            ProtocolError::FrameTooLarge { size, limit } => write!(
+                f,
+                "Frame body of {} bytes exceeds the limit of {} bytes",
+                size, limit
+            ),
+            ProtocolError::BufferOverflow { size, limit } => write!(
+                f,
+                "Read buffer of {} bytes exceeds the limit of {} bytes",
+                size, limit
+            ),
         }
     }
 }
 
-impl Default for ProtocolBody {
+impl std::error::Error for ProtocolError {}
+
+/// limits protecting a connection against oversized or hostile frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameLimits {
+    // largest accepted frame body in bytes, checked against the protocol head before
+    // the body is buffered and against the decompressed body
+    pub max_frame_size: usize,
+    // largest number of undecoded bytes buffered for a connection,
+    // must leave room for a frame of max_frame_size with its head and end
+    pub max_buffer_size: usize,
+}
+
+impl FrameLimits {
+    pub fn new(max_frame_size: usize, max_buffer_size: usize) -> Self {
+        Self {
+            max_frame_size,
+            max_buffer_size,
+        }
+    }
+    // global frame limits
+    pub fn get() -> Self {
+        *FRAME_LIMITS.read().unwrap()
+    }
+    pub fn set(limits: FrameLimits) {
+        *FRAME_LIMITS.write().unwrap() = limits;
+    }
+}
+
+impl Default for FrameLimits {
     fn default() -> Self {
         Self {
-            message: MessageDTO::default(),
+            max_frame_size: 16 * 1024 * 1024,
+            max_buffer_size: 32 * 1024 * 1024,
         }
     }
 }
 
-#[derive(Encode, Decode, PartialEq, Debug, Clone)]
-pub struct ProtocolEnd {
-    flag: String,
+/// result of reading from a connection into the decoder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadState {
+    // everything available has been read
+    Drained,
+    // the read buffer reached its limit, decode the buffered frames before reading more
+    Full,
+    // the peer has closed the connection
+    Closed,
 }
 
-impl ProtocolEnd {
+/// decode state of a connection, kept between readable events.
+enum DecodeState {
+    // waiting for a complete protocol head.
+    Head,
+    // the protocol head has been decoded, waiting for the body and the end.
+    // the checksum of the encoded head is kept to verify the frame.
+    Body(ProtocolHead, u32),
+}
+
+/// per-connection incremental protocol decoder.
+/// bytes that do not form a complete frame yet are kept until the next readable event,
+/// so a frame split across tcp segments is resumed instead of being dropped.
+pub struct ProtocolDecoder {
+    buf: Vec<u8>,
+    state: DecodeState,
+    limits: FrameLimits,
+}
+
+impl ProtocolDecoder {
+    // a decoder using the global frame limits.
     pub fn new() -> Self {
+        Self::with_limits(FrameLimits::get())
+    }
+    pub fn with_limits(limits: FrameLimits) -> Self {
         Self {
-            flag: PROTOCOL_END_IDENTIFIER.to_string(),
+            buf: Vec::new(),
+            state: DecodeState::Head,
+            limits,
         }
     }
-    // protocol end reader
-    pub fn reader(r: &mut BufReader<&TcpStream>) -> Result<ProtocolEnd, String> {
-        match r.fill_buf() {
-            Ok(buf) => {
-                let buf_len = buf.len();
-                let end_len = serialized_size(PROTOCOL_END_IDENTIFIER);
-                if buf_len >= end_len {
-                    let end_identifier_buf = &buf[..end_len];
-                    match decode::<String>(end_identifier_buf) {
-                        Ok(end_identifier) => {
-                            if PROTOCOL_END_IDENTIFIER
-                                .to_string()
-                                .eq(&end_identifier.to_string())
-                            {
-                                r.consume(end_len);
-                                return Ok(ProtocolEnd::new());
-                            } else {
-                                r.consume(end_len);
-                                return Err(
-                                    "The ending mark does not meet the requirements".to_string()
-                                );
+    // number of bytes buffered but not yet decoded.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+    // append raw bytes to the decode buffer.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+    // number of bytes that can be buffered before the read buffer is full.
+    pub fn remaining_capacity(&self) -> usize {
+        self.limits.max_buffer_size.saturating_sub(self.buf.len())
+    }
+    // read from a non-blocking source until everything available has been read,
+    // the peer has closed the connection or the read buffer is full.
+    pub fn read_from(&mut self, mut r: impl Read) -> io::Result<ReadState> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            if self.buf.len() >= self.limits.max_buffer_size {
+                return Ok(ReadState::Full);
+            }
+            let len = chunk
+                .len()
+                .min(self.limits.max_buffer_size - self.buf.len());
+            match r.read(&mut chunk[..len]) {
+                Ok(0) => return Ok(ReadState::Closed),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(ReadState::Drained),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    // decode the next complete frame from the buffer.
+    // Ok(None) means more data is needed, Err means a protocol violation was found
+    // and the offending bytes were discarded, decoding can continue with the next frame
+    // unless the error is fatal.
+    pub fn decode(&mut self) -> Result<Option<Protocol>, ProtocolError> {
+        match self.decode_frame()? {
+            None if self.buf.len() >= self.limits.max_buffer_size => {
+                let size = self.buf.len();
+                self.buf.clear();
+                self.state = DecodeState::Head;
+                Err(ProtocolError::BufferOverflow {
+                    size,
+                    limit: self.limits.max_buffer_size,
+                })
+            }
+            frame => Ok(frame),
+        }
+    }
+    // decode the next frame, frames with recoverable protocol violations are logged and skipped.
+    // fatal violations are returned as InvalidData errors carrying the ProtocolError.
+    pub fn next_frame(&mut self) -> io::Result<Option<Protocol>> {
+        loop {
+            match self.decode() {
+                Ok(frame) => return Ok(frame),
+                Err(e) if e.is_fatal() => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                Err(e) => {
+                    event!(Level::WARN, "{}", e);
+                }
+            }
+        }
+    }
+    fn decode_frame(&mut self) -> Result<Option<Protocol>, ProtocolError> {
+        loop {
+            match &self.state {
+                DecodeState::Head => {
+                    if self.buf.is_empty() {
+                        return Ok(None);
+                    }
+                    let identifier = identifier_bytes();
+                    let prefix_len = identifier.len().min(self.buf.len());
+                    if self.buf[..prefix_len] != identifier[..prefix_len] {
+                        self.resync();
+                        return Err(ProtocolError::InvalidHead(
+                            "The protocol identifier does not meet the requirements".to_string(),
+                        ));
+                    }
+                    match decode_with_len::<ProtocolHead>(&self.buf) {
+                        Ok((head, len)) => {
+                            // checked before the body is buffered
+                            let size = head.data_size as usize;
+                            if size > self.limits.max_frame_size {
+                                self.buf.clear();
+                                return Err(ProtocolError::FrameTooLarge {
+                                    size,
+                                    limit: self.limits.max_frame_size,
+                                });
                             }
+                            let checksum = crc32c::crc32c(&self.buf[..len]);
+                            self.buf.drain(..len);
+                            self.state = DecodeState::Body(head, checksum);
                         }
+                        Err(DecodeError::UnexpectedEnd { .. }) => return Ok(None),
                         Err(e) => {
-                            r.consume(end_len);
-                            return Err(format!("End identifier serialization exception: {:?}", e)
-                                .to_string());
+                            self.resync();
+                            return Err(ProtocolError::InvalidHead(format!("{:?}", e)));
                         }
                     }
-                } else {
-                    r.consume(end_len);
-                    return Err("dirty data.".to_string());
                 }
-            }
-            Err(_) => {
-                return Err("Not enough data in buffer".to_string());
+                DecodeState::Body(head, head_checksum) => {
+                    let body_size = head.data_size as usize;
+                    if self.buf.len() < body_size {
+                        return Ok(None);
+                    }
+                    let (end, end_len) =
+                        match decode_with_len::<ProtocolEnd>(&self.buf[body_size..]) {
+                            Ok(end) => end,
+                            Err(DecodeError::UnexpectedEnd { .. }) => return Ok(None),
+                            Err(e) => {
+                                self.state = DecodeState::Head;
+                                self.buf.drain(..body_size);
+                                self.resync();
+                                return Err(ProtocolError::InvalidEnd(format!("{:?}", e)));
+                            }
+                        };
+                    let checksum = crc32c::crc32c_append(*head_checksum, &self.buf[..body_size]);
+                    let body = if checksum != end.checksum {
+                        Err(ProtocolError::Corrupted {
+                            expected: checksum,
+                            actual: end.checksum,
+                        })
+                    } else {
+                        match CompressionCodec::from_code(head.compression) {
+                            Some(CompressionCodec::None) => {
+                                ProtocolBody::build(&self.buf[..body_size])
+                            }
+                            Some(codec) => codec
+                                .decompress(&self.buf[..body_size], self.limits.max_frame_size)
+                                .and_then(|bytes| ProtocolBody::build(&bytes)),
+                            None => Err(format!("Unknown compression codec {}", head.compression)),
+                        }
+                        .map_err(ProtocolError::InvalidBody)
+                    };
+                    let head = match std::mem::replace(&mut self.state, DecodeState::Head) {
+                        DecodeState::Body(head, _) => head,
+                        DecodeState::Head => unreachable!(),
+                    };
+                    // the frame boundary is known, so a broken frame is skipped as a whole
+                    // and decoding resumes with the next frame.
+                    self.buf.drain(..body_size + end_len);
+                    let body = body?;
+                    return Ok(Some(Protocol { head, body, end }));
+                }
             }
         }
     }
+    // skip to the next possible protocol head after a protocol violation.
+    fn resync(&mut self) {
+        let identifier = identifier_bytes();
+        let skip = (1..self.buf.len())
+            .find(|&i| {
+                let n = identifier.len().min(self.buf.len() - i);
+                self.buf[i..i + n] == identifier[..n]
+            })
+            .unwrap_or(self.buf.len());
+        self.buf.drain(..skip);
+    }
 }
 
-impl Default for ProtocolEnd {
+impl Default for ProtocolDecoder {
     fn default() -> Self {
-        Self {
-            flag: PROTOCOL_END_IDENTIFIER.to_string(),
-        }
+        Self::new()
     }
 }
+
+// the encoded protocol identifier every frame starts with.
+fn identifier_bytes() -> Vec<u8> {
+    encode(PROTOCOL_IDENTIFIER)
+}
@@ -0,0 +1,22 @@
+use crate::handshake::{Capabilities, Hello};
+
+/// state of an established connection, created by a successful handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    // negotiated protocol version
+    pub version: u16,
+    // capabilities supported by both sides
+    pub capabilities: Capabilities,
+}
+
+impl Session {
+    pub fn new(hello: &Hello) -> Self {
+        Self {
+            version: hello.version,
+            capabilities: hello.capabilities,
+        }
+    }
+    pub fn supports(&self, capabilities: Capabilities) -> bool {
+        self.capabilities.contains(capabilities)
+    }
+}
@@ -0,0 +1,180 @@
+/// delayed and scheduled message delivery.
+/// messages with a future delivery time are held in a hierarchical timing wheel outside the
+/// channel queues and enqueued into their channel when due.
+/// the wheel lives in memory only, scheduled messages do not survive a restart yet.
+use std::{
+    mem,
+    sync::{Mutex, Once},
+    thread,
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use tracing::{Level, event};
+
+use crate::{group::Groups, msg::Message, tool::time::now_millis};
+
+// milliseconds per tick of the lowest level.
+pub const TICK_MILLIS: u64 = 10;
+const SLOT_BITS: u64 = 6;
+// slots per level
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+// 64^6 ticks of 10ms, about 21 years. later deadlines wait in the overflow list.
+const LEVELS: usize = 6;
+
+lazy_static! {
+    // messages waiting for their delivery time.
+    static ref SCHEDULER: Mutex<TimingWheel<Message>> = Mutex::new(TimingWheel::new(now_millis()));
+    static ref SCHEDULER_START: Once = Once::new();
+}
+
+/// hierarchical timing wheel, level n has 64 slots of 64^n ticks each.
+/// an entry is kept in the lowest level able to hold its deadline and moves down a level
+/// each time the level above turns over, so inserting and expiring are O(1) per level.
+pub struct TimingWheel<T> {
+    levels: Vec<Vec<Vec<(u64, T)>>>,
+    // entries further away than the top level
+    overflow: Vec<(u64, T)>,
+    // the current tick, every entry with an earlier tick has expired
+    current: u64,
+    len: usize,
+}
+
+impl<T> TimingWheel<T> {
+    // a wheel whose time starts at now, in milliseconds.
+    pub fn new(now: u64) -> Self {
+        Self {
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            overflow: Vec::new(),
+            current: now / TICK_MILLIS,
+            len: 0,
+        }
+    }
+    // schedule an entry at a deadline in milliseconds, rounded up to a whole tick so it never
+    // expires early. past deadlines expire on the next advance.
+    pub fn insert(&mut self, deadline: u64, value: T) {
+        self.len += 1;
+        self.place(deadline.div_ceil(TICK_MILLIS).max(self.current), value);
+    }
+    // move the time forward to now, in milliseconds, and return the expired entries
+    // ordered by deadline.
+    pub fn advance(&mut self, now: u64) -> Vec<T> {
+        let target = now / TICK_MILLIS;
+        let mut expired = Vec::new();
+        if self.len == 0 {
+            self.current = self.current.max(target);
+            return expired;
+        }
+        while self.current <= target {
+            // nothing happens before the next turn of the lowest non-empty level
+            let level = self.lowest_level();
+            let span = 1 << (SLOT_BITS * level as u64);
+            let next = self.current.div_ceil(span) * span;
+            if next > target {
+                self.current = target + 1;
+                break;
+            }
+            self.current = next;
+            // bring the entries of the slots that turned over down a level
+            for level in (1..LEVELS).rev() {
+                if self.current & ((1 << (SLOT_BITS * level as u64)) - 1) == 0 {
+                    self.cascade(level);
+                }
+            }
+            if self.current & ((1 << (SLOT_BITS * LEVELS as u64)) - 1) == 0 {
+                for (tick, value) in mem::take(&mut self.overflow) {
+                    self.place(tick, value);
+                }
+            }
+            let slot = (self.current & SLOT_MASK) as usize;
+            let mut due = mem::take(&mut self.levels[0][slot]);
+            self.len -= due.len();
+            due.sort_by_key(|(tick, _)| *tick);
+            expired.extend(due.into_iter().map(|(_, value)| value));
+            if self.len == 0 {
+                self.current = target + 1;
+                break;
+            }
+            self.current += 1;
+        }
+        expired
+    }
+    // number of scheduled entries
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    // the lowest level holding an entry, LEVELS if only the overflow list does.
+    fn lowest_level(&self) -> usize {
+        self.levels
+            .iter()
+            .position(|slots| slots.iter().any(|slot| !slot.is_empty()))
+            .unwrap_or(LEVELS)
+    }
+    fn cascade(&mut self, level: usize) {
+        let slot = ((self.current >> (SLOT_BITS * level as u64)) & SLOT_MASK) as usize;
+        for (tick, value) in mem::take(&mut self.levels[level][slot]) {
+            self.place(tick, value);
+        }
+    }
+    fn place(&mut self, tick: u64, value: T) {
+        let delta = tick - self.current;
+        for level in 0..LEVELS {
+            if delta < 1 << (SLOT_BITS * (level as u64 + 1)) {
+                let slot = ((tick >> (SLOT_BITS * level as u64)) & SLOT_MASK) as usize;
+                self.levels[level][slot].push((tick, value));
+                return;
+            }
+        }
+        self.overflow.push((tick, value));
+    }
+}
+
+pub struct Scheduler;
+impl Scheduler {
+    // hold a message until its delivery time.
+    pub fn schedule(message: Message) {
+        SCHEDULER
+            .lock()
+            .unwrap()
+            .insert(message.deliver_at, message);
+    }
+    // number of messages waiting for their delivery time.
+    pub fn len() -> usize {
+        SCHEDULER.lock().unwrap().len()
+    }
+    // start enqueueing due messages into their channels, only the first call starts the timer.
+    pub fn start() {
+        SCHEDULER_START.call_once(|| {
+            thread::spawn(|| {
+                loop {
+                    thread::sleep(Duration::from_millis(TICK_MILLIS));
+                    let due = SCHEDULER.lock().unwrap().advance(now_millis());
+                    for message in due {
+                        deliver(message);
+                    }
+                }
+            });
+        });
+    }
+}
+
+// enqueue a due message into its channel.
+fn deliver(message: Message) {
+    match Groups::get_channel(message.group_id.clone(), message.topic.name.clone()) {
+        Ok(channel) => channel.write().unwrap().enqueue(message),
+        Err(e) => {
+            event!(
+                Level::WARN,
+                "scheduled message {} dropped: {}",
+                message.id,
+                e.message
+            );
+        }
+    }
+}
@@ -18,6 +18,9 @@ pub mod common {
 
 pub mod codec {
     use bincode::{Decode, Encode, config, error::DecodeError};
+    // upper bound of the bytes a single decode may claim for strings and collections,
+    // a forged length prefix fails instead of allocating the announced size.
+    pub const DECODE_LIMIT: usize = 64 * 1024 * 1024;
     // coding
     pub fn encode<T>(t: T) -> Vec<u8>
     where
@@ -32,7 +35,7 @@ pub mod codec {
     where
         T: Decode<()>,
     {
-        let config = config::standard();
+        let config = config::standard().with_limit::<DECODE_LIMIT>();
         match bincode::decode_from_slice::<T, _>(&bytes[..], config) {
             Ok((decoded, len)) => return Ok(decoded),
             Err(e) => {
@@ -40,6 +43,14 @@ pub mod codec {
             }
         }
     }
+    // decoding, also returns the number of bytes consumed from the slice.
+    pub fn decode_with_len<T>(bytes: &[u8]) -> Result<(T, usize), DecodeError>
+    where
+        T: Decode<()>,
+    {
+        let config = config::standard().with_limit::<DECODE_LIMIT>();
+        bincode::decode_from_slice::<T, _>(bytes, config)
+    }
     // The serialized size of type T in bytes
     pub fn serialized_size<T>(t: T) -> usize
     where
@@ -48,3 +59,38 @@ pub mod codec {
         encode(t).len()
     }
 }
+
+pub mod time {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    // milliseconds since the unix epoch.
+    pub fn now_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+pub mod net {
+    use std::{
+        io::{self, Write},
+        thread,
+        time::Duration,
+    };
+    // write the whole buffer to a non-blocking stream, waiting while the socket buffer is full.
+    // used by clients, the broker writes through the outbound buffer of a connection.
+    pub fn write_all<W: Write>(mut stream: W, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match stream.write(buf) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => buf = &buf[n..],
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(1))
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
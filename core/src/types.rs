@@ -1,25 +1,34 @@
 use std::{
-    collections::{HashMap, VecDeque},
-    sync::Mutex,
+    collections::{HashMap, HashSet, VecDeque},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex},
 };
 
 use lazy_static::lazy_static;
 use mio::{Token, net::TcpStream};
 use rand::seq::IndexedRandom;
 
-use crate::{msg::Message, tool::common::get_keys_for_value};
+use crate::{connection::Connection, msg::Message, session::Session};
 
 lazy_static! {
       // consumer binder.
     // k: (group id,topic) v: function name
     static ref CONSUMER_BINDER: Mutex<HashMap< (String, String), fn(Message) -> Result<String, String>>> = Mutex::new(HashMap::< (String, String), fn(Message) -> Result<String, String>>::default());
     // connection pool
-    static ref CONNECTION_POOL: Mutex<HashMap<Token, Mutex<TcpStream>>> = Mutex::new(HashMap::<Token, Mutex<TcpStream>>::default());
-    // connection pool and gourp bind
-    // k: token v: (group id, topic)
-    static ref CONNECTION_POOL_GROUP_BIND: Mutex<HashMap<Token, (String, String)>> = Mutex::new(HashMap::<Token, (String, String)>::default());
+    static ref CONNECTION_POOL: Mutex<HashMap<Token, Arc<Connection>>> = Mutex::new(HashMap::<Token, Arc<Connection>>::default());
+    // connection pool and gourp bind, a connection can subscribe to many (group id, topic).
+    static ref CONNECTION_POOL_GROUP_BIND: Mutex<GroupBind> = Mutex::new(GroupBind::default());
+    // connection session, only exists for connections that completed the handshake.
+    // k: token v: session
+    static ref CONNECTION_SESSION: Mutex<HashMap<Token, Session>> = Mutex::new(HashMap::<Token, Session>::default());
+    // messages sent within the open transaction of a connection.
+    // k: token v: staged messages
+    static ref CONNECTION_TRANSACTION: Mutex<HashMap<Token, Vec<Message>>> = Mutex::new(HashMap::<Token, Vec<Message>>::default());
 }
 
+// maximum number of messages staged by one transaction.
+pub const MAX_TRANSACTION_SIZE: usize = 10_000;
+
 pub struct ConsumerBinder;
 impl ConsumerBinder {
     pub fn insert(k: (String, String), v: fn(Message) -> Result<String, String>) {
@@ -33,23 +42,35 @@ impl ConsumerBinder {
             return Err("key does not exist.".to_string());
         }
     }
+    // (group id, topic) of every bound consumer function.
+    pub fn keys() -> Vec<(String, String)> {
+        CONSUMER_BINDER.lock().unwrap().keys().cloned().collect()
+    }
 }
 
 pub struct ConnectionPool;
 impl ConnectionPool {
     // Handle the connection source of the specified token
-    pub fn handle(token: &Token, handler: impl Fn(&mut TcpStream)) {
-        match CONNECTION_POOL.lock().unwrap().get_mut(token) {
-            Some(stream) => {
-                handler(&mut stream.lock().unwrap());
-            }
+    // the pool is not locked while the handler runs.
+    pub fn handle<R>(token: &Token, handler: impl FnOnce(&Connection) -> R) -> Option<R> {
+        let connection = CONNECTION_POOL.lock().unwrap().get(token).cloned();
+        match connection {
+            Some(connection) => Some(handler(&connection)),
             None => {
                 // No connection source exists
+                None
             }
         }
     }
+    // whether pushes to the connection wait for the peer to read, see `Connection::is_congested`.
+    pub fn is_congested(token: &Token) -> bool {
+        ConnectionPool::handle(token, |connection| connection.is_congested()).unwrap_or(false)
+    }
     pub fn insert(k: Token, v: TcpStream) {
-        CONNECTION_POOL.lock().unwrap().insert(k, Mutex::new(v));
+        CONNECTION_POOL
+            .lock()
+            .unwrap()
+            .insert(k, Arc::new(Connection::new(v)));
     }
     pub fn remove(k: Token) {
         CONNECTION_POOL.lock().unwrap().remove(&k);
@@ -57,17 +78,139 @@ impl ConnectionPool {
     pub fn writer(group_id: String, topic: String) {}
 }
 
+// subscriptions indexed in both directions, kept consistent under one lock.
+#[derive(Default)]
+struct GroupBind {
+    // k: token v: subscribed (group id, topic)
+    by_token: HashMap<Token, HashSet<(String, String)>>,
+    // k: (group id, topic) v: subscribed tokens
+    by_channel: HashMap<(String, String), Vec<Token>>,
+}
+
 pub struct ConnectionPoolAndGroupBind;
 impl ConnectionPoolAndGroupBind {
     pub fn get_token_list(v: (String, String)) -> Vec<Token> {
-        let token_list = get_keys_for_value(CONNECTION_POOL_GROUP_BIND.lock().unwrap(), v);
-        token_list
+        let bind = CONNECTION_POOL_GROUP_BIND.lock().unwrap();
+        bind.by_channel.get(&v).cloned().unwrap_or_default()
     }
     pub fn get_random_token(v: (String, String)) -> Option<Token> {
-        let token_list = get_keys_for_value(CONNECTION_POOL_GROUP_BIND.lock().unwrap(), v);
-        token_list.choose(&mut rand::rng()).copied()
+        let bind = CONNECTION_POOL_GROUP_BIND.lock().unwrap();
+        bind.by_channel
+            .get(&v)
+            .and_then(|tokens| tokens.choose(&mut rand::rng()).copied())
+    }
+    // rendezvous hashing, a key goes to the connection with the highest weight for it.
+    // if a connection leaves only its keys move, if one joins it takes keys evenly from the others.
+    pub fn route(key: &str, tokens: &[Token]) -> Option<Token> {
+        tokens
+            .iter()
+            .max_by_key(|token| {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                token.0.hash(&mut hasher);
+                hasher.finish()
+            })
+            .copied()
     }
+    // (group id, topic) subscribed by the connection.
+    pub fn get(k: &Token) -> Vec<(String, String)> {
+        let bind = CONNECTION_POOL_GROUP_BIND.lock().unwrap();
+        bind.by_token
+            .get(k)
+            .map(|channels| channels.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+    // subscribe the connection to the group and topic, existing subscriptions are kept.
     pub fn insert(k: Token, v: (String, String)) {
-        CONNECTION_POOL_GROUP_BIND.lock().unwrap().insert(k, v);
+        let mut bind = CONNECTION_POOL_GROUP_BIND.lock().unwrap();
+        if bind.by_token.entry(k).or_default().insert(v.clone()) {
+            bind.by_channel.entry(v).or_default().push(k);
+        }
+    }
+    // remove every subscription of the connection.
+    pub fn remove(k: &Token) {
+        let mut bind = CONNECTION_POOL_GROUP_BIND.lock().unwrap();
+        if let Some(channels) = bind.by_token.remove(k) {
+            for v in channels {
+                bind.remove_token(&v, k);
+            }
+        }
+    }
+    // remove the subscription of the connection to the group and topic.
+    pub fn unbind(k: &Token, v: &(String, String)) {
+        let mut bind = CONNECTION_POOL_GROUP_BIND.lock().unwrap();
+        let removed = match bind.by_token.get_mut(k) {
+            Some(channels) => {
+                let removed = channels.remove(v);
+                if channels.is_empty() {
+                    bind.by_token.remove(k);
+                }
+                removed
+            }
+            None => false,
+        };
+        if removed {
+            bind.remove_token(v, k);
+        }
+    }
+}
+
+impl GroupBind {
+    fn remove_token(&mut self, v: &(String, String), k: &Token) {
+        if let Some(tokens) = self.by_channel.get_mut(v) {
+            tokens.retain(|token| token != k);
+            if tokens.is_empty() {
+                self.by_channel.remove(v);
+            }
+        }
+    }
+}
+
+pub struct ConnectionSession;
+impl ConnectionSession {
+    pub fn get(k: &Token) -> Option<Session> {
+        CONNECTION_SESSION.lock().unwrap().get(k).cloned()
+    }
+    pub fn insert(k: Token, v: Session) {
+        CONNECTION_SESSION.lock().unwrap().insert(k, v);
+    }
+    pub fn remove(k: &Token) {
+        CONNECTION_SESSION.lock().unwrap().remove(k);
+    }
+    // whether the connection completed the handshake.
+    pub fn is_established(k: &Token) -> bool {
+        CONNECTION_SESSION.lock().unwrap().contains_key(k)
+    }
+}
+
+pub struct ConnectionTransaction;
+impl ConnectionTransaction {
+    // open a transaction, false if the connection already has one.
+    pub fn begin(k: Token) -> bool {
+        let mut transactions = CONNECTION_TRANSACTION.lock().unwrap();
+        if transactions.contains_key(&k) {
+            return false;
+        }
+        transactions.insert(k, Vec::new());
+        true
+    }
+    pub fn is_open(k: &Token) -> bool {
+        CONNECTION_TRANSACTION.lock().unwrap().contains_key(k)
+    }
+    // stage messages in the open transaction, false if the transaction would exceed
+    // MAX_TRANSACTION_SIZE. nothing is staged then.
+    pub fn stage(k: &Token, v: Vec<Message>) -> bool {
+        let mut transactions = CONNECTION_TRANSACTION.lock().unwrap();
+        match transactions.get_mut(k) {
+            Some(staged) if staged.len() + v.len() <= MAX_TRANSACTION_SIZE => {
+                staged.extend(v);
+                true
+            }
+            _ => false,
+        }
+    }
+    // close the transaction, returns its staged messages or None if it was not open.
+    pub fn remove(k: &Token) -> Option<Vec<Message>> {
+        CONNECTION_TRANSACTION.lock().unwrap().remove(k)
     }
 }
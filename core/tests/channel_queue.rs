@@ -0,0 +1,103 @@
+// the message queue of a channel, driven through the channel api without consumers.
+use std::time::Duration;
+
+use luminmq_core::{
+    channel::{Channel, ChannelConfig, ChannelMode},
+    msg::Message,
+};
+
+use common::{GROUP_ID, TOPIC, message_with_text};
+
+mod common;
+
+fn message(text: &str, priority: u8) -> Message {
+    let mut message = message_with_text(GROUP_ID, TOPIC, text);
+    message.set_priority(priority);
+    message
+}
+
+// a pull channel, nothing is delivered unless dequeued.
+fn channel(config: ChannelConfig) -> Channel {
+    common::channel(ChannelMode::Pull, config)
+}
+
+fn drain(channel: &mut Channel) -> Vec<String> {
+    let mut texts = Vec::new();
+    while let Some(message) = channel.dequeue() {
+        texts.push(message.text_lossy().to_string());
+    }
+    texts
+}
+
+#[test]
+fn single_level_ignores_priority() {
+    let mut channel = channel(ChannelConfig::default());
+    channel.enqueue(message("a", 0));
+    channel.enqueue(message("b", 9));
+    channel.enqueue(message("c", 3));
+    assert_eq!(drain(&mut channel), ["a", "b", "c"]);
+}
+
+#[test]
+fn higher_levels_overtake_and_each_level_is_fifo() {
+    let mut channel = channel(ChannelConfig {
+        priority_levels: 3,
+        ..Default::default()
+    });
+    channel.enqueue(message("bulk-1", 0));
+    channel.enqueue(message("normal-1", 1));
+    channel.enqueue_batch(vec![message("bulk-2", 0), message("urgent-1", 2)]);
+    // above the highest level, queued with the highest level
+    channel.enqueue(message("urgent-2", 200));
+    channel.enqueue(message("normal-2", 1));
+    assert_eq!(channel.message_num(), 6);
+    assert_eq!(
+        drain(&mut channel),
+        ["urgent-1", "urgent-2", "normal-1", "normal-2", "bulk-1", "bulk-2"]
+    );
+    assert!(channel.is_empty());
+}
+
+#[test]
+fn expired_messages_are_skipped_in_every_level() {
+    let mut channel = channel(ChannelConfig {
+        priority_levels: 2,
+        ..Default::default()
+    });
+    let mut expiring = message("expiring", 1);
+    expiring.set_ttl(Duration::from_millis(1));
+    channel.enqueue(expiring);
+    channel.enqueue(message("kept", 0));
+    std::thread::sleep(Duration::from_millis(5));
+    assert_eq!(drain(&mut channel), ["kept"]);
+}
+
+#[test]
+fn duplicate_sends_return_the_first_id() {
+    let mut channel = channel(ChannelConfig {
+        dedup_window: 2,
+        ..Default::default()
+    });
+    let send = |channel: &mut Channel, sequence: u64, id: u64| {
+        let mut message = message("order", 0);
+        message.set_producer("producer-1", sequence);
+        message.id = id;
+        channel.deduplicate(&message)
+    };
+    assert_eq!(send(&mut channel, 1, 100), None);
+    assert_eq!(send(&mut channel, 1, 101), Some(100));
+    assert_eq!(send(&mut channel, 2, 102), None);
+    // another producer may use the same sequence
+    let mut other = message("order", 0);
+    other.set_producer("producer-2", 2);
+    other.id = 103;
+    assert_eq!(channel.deduplicate(&other), None);
+    // the window only remembers the last two sends
+    assert_eq!(send(&mut channel, 1, 104), None);
+    assert_eq!(send(&mut channel, 2, 105), None);
+    // messages without a producer are never duplicates
+    let mut plain = message("order", 0);
+    plain.id = 106;
+    assert_eq!(channel.deduplicate(&plain), None);
+    assert_eq!(channel.deduplicate(&plain), None);
+}
@@ -0,0 +1,53 @@
+// fixtures shared by the tests of channels, each test uses a part of them.
+#![allow(dead_code)]
+
+use mio::Token;
+
+use luminmq_core::{
+    channel::{Channel, ChannelConfig, ChannelMode},
+    group::GroupMode,
+    msg::{ConsumerType, Message, MessageStatus, MessageType},
+};
+
+pub const GROUP_ID: &str = "group-test";
+pub const TOPIC: &str = "topic-test";
+
+// a channel of the test topic in a cluster group, its loop is not started.
+pub fn channel(mode: ChannelMode, config: ChannelConfig) -> Channel {
+    Channel::with_config(
+        TOPIC.to_string(),
+        GROUP_ID.to_string(),
+        mode,
+        GroupMode::Cluster,
+        config,
+    )
+}
+
+// a message sent by a producer to a topic.
+pub fn message_with_text(group_id: &str, topic: &str, text: &str) -> Message {
+    Message::new(
+        group_id.to_string(),
+        topic.to_string(),
+        text,
+        MessageType::Business,
+        ConsumerType::Send,
+        MessageStatus::Success,
+    )
+}
+
+// a message with the id assigned by the broker, its text is "message-<id>".
+pub fn message(group_id: &str, topic: &str, id: u64) -> Message {
+    let mut message = message_with_text(group_id, topic, &format!("message-{}", id));
+    message.id = id;
+    message
+}
+
+// deliver the queued messages to one consumer that negotiated acks, returns their ids.
+pub fn deliver(channel: &Channel, token: Token) -> Vec<u64> {
+    channel
+        .take_deliveries(&[token], |_| true)
+        .into_iter()
+        .flat_map(|(_, messages)| messages)
+        .map(|message| message.id)
+        .collect()
+}
@@ -0,0 +1,55 @@
+// writes to a peer that stops reading are buffered per connection and never block.
+use std::{
+    io::Read,
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use luminmq_core::connection::{Connection, MAX_OUTBOUND_BUFFER_SIZE, MAX_PENDING_PUSH_SIZE};
+
+const FRAME_SIZE: usize = 64 * 1024;
+
+// a connection of the broker and the socket of its peer.
+fn connect() -> (Connection, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (peer, _) = listener.accept().unwrap();
+    stream.set_nonblocking(true).unwrap();
+    let connection = Connection::new(mio::net::TcpStream::from_std(stream));
+    (connection, peer)
+}
+
+#[test]
+fn frames_are_buffered_while_the_peer_does_not_read() {
+    let (connection, mut peer) = connect();
+    // fill the socket buffers and the outbound buffer, every frame is accepted whole or rejected
+    let mut sent = 0;
+    loop {
+        let frame = vec![(sent % 251) as u8; FRAME_SIZE];
+        if connection.send(&frame).is_err() {
+            break;
+        }
+        sent += 1;
+        assert!(sent < 2 * MAX_OUTBOUND_BUFFER_SIZE / FRAME_SIZE);
+    }
+    assert!(connection.is_congested());
+    assert!(connection.pending() > MAX_PENDING_PUSH_SIZE);
+    assert!(connection.pending() <= MAX_OUTBOUND_BUFFER_SIZE);
+
+    // the peer reads every accepted frame in order once the buffer is flushed
+    let reader = thread::spawn(move || {
+        let mut received = vec![0; sent * FRAME_SIZE];
+        peer.read_exact(&mut received).unwrap();
+        received
+    });
+    while connection.pending() > 0 {
+        connection.flush().unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert!(!connection.is_congested());
+    let received = reader.join().unwrap();
+    for (i, frame) in received.chunks(FRAME_SIZE).enumerate() {
+        assert!(frame.iter().all(|b| *b == (i % 251) as u8));
+    }
+}
@@ -0,0 +1,84 @@
+// messages settled without an ack too often are moved to the dead-letter channel of their topic.
+use mio::Token;
+
+use luminmq_core::{
+    channel::{
+        ChannelConfig, ChannelMode, HEADER_DELIVERY_ATTEMPTS, HEADER_LAST_FAILURE,
+        HEADER_ORIGINAL_TOPIC, dead_letter,
+    },
+    error::ErrorCode,
+    group::{GroupMode, Groups},
+};
+
+use common::{deliver, message};
+
+mod common;
+
+#[tokio::test]
+async fn messages_are_dead_lettered_after_the_max_delivery_attempts() {
+    Groups::default_insert_group("group-dlq".to_string(), GroupMode::Cluster);
+    Groups::insert_channel_with_config(
+        "group-dlq".to_string(),
+        "orders".to_string(),
+        ChannelMode::Pull,
+        ChannelConfig::new(2, None),
+    )
+    .unwrap();
+    let channel = Groups::get_channel("group-dlq".to_string(), "orders".to_string()).unwrap();
+    channel
+        .write()
+        .unwrap()
+        .enqueue(message("group-dlq", "orders", 1));
+
+    assert_eq!(deliver(&channel.read().unwrap(), Token(1)), [1]);
+    let dead_letters = channel.read().unwrap().nack(1, Token(1), "boom");
+    assert!(dead_letters.is_empty());
+    assert_eq!(deliver(&channel.read().unwrap(), Token(1)), [1]);
+    let dead_letters = channel.read().unwrap().nack(1, Token(1), "boom again");
+    assert_eq!(dead_letters.len(), 1);
+    assert!(channel.read().unwrap().is_empty());
+
+    dead_letter(dead_letters);
+    let dead_letter_channel =
+        Groups::get_channel("group-dlq".to_string(), "orders.dlq".to_string()).unwrap();
+    let message = dead_letter_channel.write().unwrap().dequeue().unwrap();
+    assert_eq!(message.id, 1);
+    assert_eq!(message.topic.name, "orders.dlq");
+    assert_eq!(message.delivery_attempts, 0);
+    assert_eq!(message.header(HEADER_LAST_FAILURE), Some("boom again"));
+    assert_eq!(message.header(HEADER_ORIGINAL_TOPIC), Some("orders"));
+    assert_eq!(message.header(HEADER_DELIVERY_ATTEMPTS), Some("2"));
+}
+
+#[tokio::test]
+async fn a_channel_cannot_dead_letter_into_itself() {
+    Groups::default_insert_group("group-self".to_string(), GroupMode::Cluster);
+    let result = Groups::insert_channel_with_config(
+        "group-self".to_string(),
+        "orders".to_string(),
+        ChannelMode::Pull,
+        ChannelConfig::new(2, Some("orders".to_string())),
+    );
+    assert_eq!(result.unwrap_err().code, ErrorCode::InvalidConfig);
+    assert!(Groups::get_channel("group-self".to_string(), "orders".to_string()).is_err());
+}
+
+#[tokio::test]
+async fn dead_letters_without_a_channel_are_dropped() {
+    Groups::default_insert_group("group-gone".to_string(), GroupMode::Cluster);
+    Groups::insert_channel(
+        "group-gone".to_string(),
+        "orders".to_string(),
+        ChannelMode::Pull,
+    );
+    // the dead-letter channel is missing, the message goes back to its original channel
+    let mut returned = message("group-gone", "orders.dlq", 1);
+    returned.set_header(HEADER_ORIGINAL_TOPIC, "orders");
+    // without its original topic the message has nowhere to go
+    let dropped = message("group-gone", "orders.dlq", 2);
+    dead_letter(vec![returned, dropped]);
+    let channel = Groups::get_channel("group-gone".to_string(), "orders".to_string()).unwrap();
+    let mut channel = channel.write().unwrap();
+    assert_eq!(channel.dequeue().unwrap().id, 1);
+    assert!(channel.is_empty());
+}
@@ -0,0 +1,140 @@
+// deliveries to a cluster of consumers, taken through the channel api without connections.
+use mio::Token;
+
+use luminmq_core::{
+    channel::{Channel, ChannelConfig, ChannelMode},
+    msg::{Message, MessageStatus},
+    types::ConnectionPoolAndGroupBind,
+};
+
+use common::{GROUP_ID, TOPIC};
+
+mod common;
+
+const TOKENS: [Token; 3] = [Token(1), Token(2), Token(3)];
+
+// a message of the test topic that was never delivered.
+fn message(id: u64, key: Option<&str>) -> Message {
+    let mut message = common::message(GROUP_ID, TOPIC, id);
+    message.status = MessageStatus::None;
+    if let Some(key) = key {
+        message.set_key(key);
+    }
+    message
+}
+
+// a push channel with the default config.
+fn channel() -> Channel {
+    common::channel(ChannelMode::Push, ChannelConfig::default())
+}
+
+// (token, ids) of the deliveries.
+fn ids(deliveries: Vec<(Token, Vec<Message>)>) -> Vec<(Token, Vec<u64>)> {
+    deliveries
+        .into_iter()
+        .map(|(token, messages)| (token, messages.iter().map(|m| m.id).collect()))
+        .collect()
+}
+
+#[test]
+fn busy_key_is_held_back_while_unkeyed_messages_go_through() {
+    let mut channel = channel();
+    let owner = ConnectionPoolAndGroupBind::route("order-1", &TOKENS).unwrap();
+    channel.enqueue(message(1, Some("order-1")));
+    channel.enqueue(message(2, Some("order-1")));
+    channel.enqueue(message(3, None));
+
+    let deliveries = channel.take_deliveries(&TOKENS, |_| true);
+    let delivered: Vec<u64> = ids(deliveries.clone())
+        .into_iter()
+        .flat_map(|(_, ids)| ids)
+        .collect();
+    assert!(delivered.contains(&1) && delivered.contains(&3));
+    assert!(!delivered.contains(&2));
+    let (token, _) = deliveries
+        .iter()
+        .find(|(_, messages)| messages.iter().any(|m| m.id == 1))
+        .unwrap();
+    assert_eq!(*token, owner);
+    assert!(
+        deliveries
+            .iter()
+            .flat_map(|(_, m)| m)
+            .all(|m| m.status == MessageStatus::Success && m.delivery_attempts == 1)
+    );
+    assert_eq!(channel.in_flight_num(), 2);
+    assert_eq!(channel.message_num(), 1);
+
+    // the key is busy until its message is settled
+    channel.enqueue(message(4, None));
+    assert_eq!(ids(channel.take_deliveries(&TOKENS, |_| true)).len(), 1);
+    assert!(channel.take_deliveries(&TOKENS, |_| true).is_empty());
+    assert!(channel.ack(1, owner));
+    assert_eq!(
+        ids(channel.take_deliveries(&TOKENS, |_| true)),
+        [(owner, vec![2])]
+    );
+    assert!(channel.is_empty());
+}
+
+#[test]
+fn each_key_has_one_message_in_flight() {
+    let mut channel = channel();
+    channel.enqueue(message(1, Some("order-1")));
+    channel.enqueue(message(2, Some("order-2")));
+    channel.enqueue(message(3, Some("order-1")));
+    channel.enqueue(message(4, Some("order-2")));
+
+    let mut delivered: Vec<u64> = ids(channel.take_deliveries(&TOKENS, |_| true))
+        .into_iter()
+        .flat_map(|(_, ids)| ids)
+        .collect();
+    delivered.sort();
+    assert_eq!(delivered, [1, 2]);
+    assert_eq!(channel.message_num(), 2);
+}
+
+#[test]
+fn keys_are_not_held_back_without_acks() {
+    let mut channel = channel();
+    let owner = ConnectionPoolAndGroupBind::route("order-1", &TOKENS).unwrap();
+    channel.enqueue(message(1, Some("order-1")));
+    channel.enqueue(message(2, Some("order-1")));
+
+    assert_eq!(
+        ids(channel.take_deliveries(&TOKENS, |_| false)),
+        [(owner, vec![1, 2])]
+    );
+    assert_eq!(channel.in_flight_num(), 0);
+    assert!(channel.is_empty());
+}
+
+#[test]
+fn route_is_sticky() {
+    let reversed: Vec<Token> = TOKENS.iter().rev().copied().collect();
+    for i in 0..100 {
+        let key = format!("order-{}", i);
+        let owner = ConnectionPoolAndGroupBind::route(&key, &TOKENS).unwrap();
+        // the same consumer whatever the order of the subscribers
+        assert_eq!(
+            ConnectionPoolAndGroupBind::route(&key, &reversed),
+            Some(owner)
+        );
+        // a key only moves if its consumer leaves
+        let others: Vec<Token> = TOKENS.iter().filter(|t| **t != owner).copied().collect();
+        let remaining: Vec<Token> = TOKENS
+            .iter()
+            .filter(|t| **t != others[0])
+            .copied()
+            .collect();
+        assert_eq!(
+            ConnectionPoolAndGroupBind::route(&key, &remaining),
+            Some(owner)
+        );
+        assert_ne!(
+            ConnectionPoolAndGroupBind::route(&key, &others),
+            Some(owner)
+        );
+    }
+    assert_eq!(ConnectionPoolAndGroupBind::route("order-1", &[]), None);
+}
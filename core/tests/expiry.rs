@@ -0,0 +1,84 @@
+// messages outliving their ttl are swept by the channel loop and routed to the expiry channel.
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use tokio::runtime::{Builder, Runtime};
+
+use luminmq_core::{
+    channel::{ChannelConfig, ChannelMode, HEADER_EXPIRED_AT, HEADER_ORIGINAL_TOPIC},
+    group::{GroupMode, Groups},
+    msg::Message,
+    tool::time::now_millis,
+};
+
+mod common;
+
+fn message(id: u64, ttl: Duration) -> Message {
+    let mut message = common::message("group-expiry", "orders", id);
+    message.set_ttl(ttl);
+    message
+}
+
+// a runtime running the channel loops. the group loop blocks a worker and never yields, the
+// runtime is leaked since dropping it would wait for the loop.
+fn runtime() -> &'static Runtime {
+    Box::leak(Box::new(
+        Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build()
+            .unwrap(),
+    ))
+}
+
+#[test]
+fn expired_messages_are_routed_to_the_expiry_channel() {
+    let _guard = runtime().enter();
+    Groups::default_insert_group("group-expiry".to_string(), GroupMode::Cluster);
+    Groups::insert_channel_with_config(
+        "group-expiry".to_string(),
+        "orders".to_string(),
+        ChannelMode::Pull,
+        ChannelConfig {
+            expiry_topic: Some("orders.expired".to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let channel = Groups::get_channel("group-expiry".to_string(), "orders".to_string()).unwrap();
+    let enqueued_at = now_millis();
+    {
+        let mut channel = channel.write().unwrap();
+        channel.enqueue(message(1, Duration::from_millis(1)));
+        // a ttl past the end of time never expires
+        channel.enqueue(message(2, Duration::from_millis(u64::MAX)));
+    }
+
+    // the message waits in the queue until the sweep finds it
+    let started = Instant::now();
+    while Groups::channel_expired_num("group-expiry".to_string(), "orders".to_string()) == 0 {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(
+        Groups::channel_expired_num("group-expiry".to_string(), "orders".to_string()),
+        1
+    );
+    let message = channel.write().unwrap().dequeue().unwrap();
+    assert_eq!(message.id, 2);
+    assert_eq!(message.expires_at, u64::MAX);
+
+    let expiry_channel =
+        Groups::get_channel("group-expiry".to_string(), "orders.expired".to_string()).unwrap();
+    let message = expiry_channel.write().unwrap().dequeue().unwrap();
+    assert_eq!(message.id, 1);
+    assert_eq!(message.topic.name, "orders.expired");
+    assert_eq!(message.header(HEADER_ORIGINAL_TOPIC), Some("orders"));
+    let expired_at: u64 = message.header(HEADER_EXPIRED_AT).unwrap().parse().unwrap();
+    assert!(expired_at > enqueued_at && expired_at <= now_millis());
+    // the expiry channel keeps the message until it is consumed
+    assert_eq!((message.ttl, message.expires_at), (0, 0));
+    assert!(expiry_channel.read().unwrap().is_empty());
+}
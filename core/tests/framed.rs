@@ -0,0 +1,99 @@
+// the frame codecs over in-memory buffers.
+use std::io::{self, Cursor};
+
+use bytes::BytesMut;
+use luminmq_core::{
+    framed::{FrameReader, FrameWriter, ProtocolCodec},
+    handshake::Hello,
+    msg::MessageDTO,
+    protocol::{FrameLimits, Protocol, ProtocolError},
+    session::Session,
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+fn protocol(data: Vec<u8>) -> Protocol {
+    let mut protocol = Protocol::default();
+    protocol.insert_message(MessageDTO::new(
+        "group-test".to_string(),
+        "topic-test".to_string(),
+        1,
+        1,
+        1,
+        data,
+    ));
+    let _ = protocol.ready();
+    protocol
+}
+
+fn data(protocol: &Protocol) -> Vec<u8> {
+    protocol.get_message().unwrap().data
+}
+
+#[test]
+fn codec_roundtrip_byte_by_byte() {
+    let mut codec = ProtocolCodec::new();
+    codec.set_session(Some(Session::new(&Hello::default())));
+    let mut encoded = BytesMut::new();
+    codec
+        .encode(protocol(b"one".to_vec()), &mut encoded)
+        .unwrap();
+    codec
+        .encode(protocol(vec![b'x'; 8192]), &mut encoded)
+        .unwrap();
+    let mut src = BytesMut::new();
+    let mut decoded = Vec::new();
+    for byte in encoded.iter() {
+        src.extend_from_slice(&[*byte]);
+        while let Some(protocol) = codec.decode(&mut src).unwrap() {
+            decoded.push(protocol);
+        }
+    }
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(data(&decoded[0]), b"one");
+    assert_eq!(data(&decoded[1]), vec![b'x'; 8192]);
+    // the large body was compressed for the session
+    assert_ne!(decoded[1].head.compression, 0);
+}
+
+#[test]
+fn codec_rejects_partial_frame_at_eof() {
+    let mut codec = ProtocolCodec::new();
+    let mut src = BytesMut::from(&protocol(b"one".to_vec()).to_byte_vec()[..10]);
+    let e = codec.decode_eof(&mut src).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn codec_rejects_oversized_frame() {
+    let mut codec = ProtocolCodec::with_limits(FrameLimits::new(1024, 4096));
+    let mut src = BytesMut::from(&protocol(vec![b'x'; 2048]).to_byte_vec()[..]);
+    let e = codec.decode(&mut src).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    let violation = e.get_ref().unwrap().downcast_ref::<ProtocolError>();
+    assert!(matches!(
+        violation,
+        Some(ProtocolError::FrameTooLarge { .. })
+    ));
+}
+
+#[test]
+fn sync_writer_and_reader_roundtrip() {
+    let mut writer = FrameWriter::new(Vec::new());
+    for i in 0..10u8 {
+        writer.write_frame(&mut protocol(vec![i; 100])).unwrap();
+    }
+    let reader = FrameReader::new(Cursor::new(writer.into_inner()));
+    let frames = reader.collect::<io::Result<Vec<_>>>().unwrap();
+    assert_eq!(frames.len(), 10);
+    for (i, frame) in frames.iter().enumerate() {
+        assert_eq!(data(frame), vec![i as u8; 100]);
+    }
+}
+
+#[test]
+fn sync_reader_rejects_truncated_stream() {
+    let bytes = protocol(b"one".to_vec()).to_byte_vec();
+    let mut reader = FrameReader::new(Cursor::new(&bytes[..bytes.len() - 1]));
+    let e = reader.read_frame().unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,77 @@
+// message ids: 42 bits milliseconds since the id epoch, 10 bits node id, 12 bits sequence.
+use luminmq_core::{
+    id::{IdGenerator, MAX_NODE_ID, MessageId},
+    tool::time::now_millis,
+};
+
+const SEQUENCE_BITS: u64 = 12;
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+
+fn node_id(id: u64) -> u64 {
+    (id >> SEQUENCE_BITS) & MAX_NODE_ID as u64
+}
+
+fn sequence(id: u64) -> u64 {
+    id & MAX_SEQUENCE
+}
+
+#[test]
+fn ids_increase_past_the_sequence_of_a_millisecond() {
+    let mut generator = IdGenerator::new(3);
+    let mut last = generator.next_id();
+    let mut borrowed = false;
+    // a burst exhausts the sequence of a millisecond and borrows the next one
+    for _ in 0..1_000_000 {
+        let id = generator.next_id();
+        assert!(id > last);
+        assert_eq!(node_id(id), 3);
+        if sequence(last) == MAX_SEQUENCE {
+            assert_eq!(sequence(id), 0);
+            assert!(MessageId::timestamp(id) > MessageId::timestamp(last));
+            borrowed = true;
+        }
+        last = id;
+        if borrowed {
+            break;
+        }
+    }
+    assert!(borrowed);
+}
+
+#[test]
+fn node_id_is_kept_in_its_bits() {
+    for node in [0, 1, 42, MAX_NODE_ID] {
+        let id = IdGenerator::new(node).next_id();
+        assert_eq!(node_id(id), node as u64);
+        assert_eq!(sequence(id), 0);
+    }
+    // larger node ids are cut to the bits they own and never reach the timestamp
+    let id = IdGenerator::new(u16::MAX).next_id();
+    assert_eq!(node_id(id), MAX_NODE_ID as u64);
+    assert!(MessageId::timestamp(id) <= now_millis());
+}
+
+#[test]
+fn timestamp_is_the_time_of_the_id() {
+    let before = now_millis();
+    let id = IdGenerator::new(1).next_id();
+    let after = now_millis();
+    let timestamp = MessageId::timestamp(id);
+    assert!(before <= timestamp && timestamp <= after);
+}
+
+#[test]
+fn set_node_id_keeps_ids_increasing() {
+    MessageId::set_node_id(MAX_NODE_ID);
+    let mut last = 0;
+    for _ in 0..10_000 {
+        let id = MessageId::next();
+        assert!(id > last);
+        last = id;
+    }
+    // a lower node id in the same millisecond must not go back
+    MessageId::set_node_id(0);
+    let id = MessageId::next();
+    assert!(id > last);
+    assert_eq!(node_id(id), 0);
+}
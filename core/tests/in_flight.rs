@@ -0,0 +1,102 @@
+// messages in flight to consumers that negotiated acks, settled through the channel api
+// without connections.
+use std::time::{Duration, Instant};
+
+use mio::Token;
+
+use luminmq_core::{
+    channel::{Channel, ChannelConfig, ChannelMode, HEADER_LAST_FAILURE},
+    msg::Message,
+};
+
+use common::{GROUP_ID, TOPIC, deliver, message};
+
+mod common;
+
+// a push channel with the default config.
+fn channel() -> Channel {
+    common::channel(ChannelMode::Push, ChannelConfig::default())
+}
+
+#[test]
+fn ack_settles_the_message_of_the_connection() {
+    let mut channel = channel();
+    channel.enqueue(message(GROUP_ID, TOPIC, 1));
+    assert_eq!(deliver(&channel, Token(1)), [1]);
+    assert_eq!(channel.in_flight_num(), 1);
+
+    // only the connection the message is in flight to can settle it
+    assert!(!channel.ack(1, Token(2)));
+    assert!(!channel.ack(2, Token(1)));
+    assert!(channel.ack(1, Token(1)));
+    assert!(!channel.ack(1, Token(1)));
+    assert_eq!(channel.in_flight_num(), 0);
+    assert!(channel.is_empty());
+}
+
+#[test]
+fn nack_redelivers_the_message_first() {
+    let mut channel = channel();
+    channel.enqueue(message(GROUP_ID, TOPIC, 1));
+    assert_eq!(deliver(&channel, Token(1)), [1]);
+    channel.enqueue(message(GROUP_ID, TOPIC, 2));
+
+    assert!(channel.nack(1, Token(1), "boom").is_empty());
+    assert_eq!(channel.in_flight_num(), 0);
+    let messages: Vec<Message> = channel
+        .take_deliveries(&[Token(1)], |_| true)
+        .into_iter()
+        .flat_map(|(_, messages)| messages)
+        .collect();
+    assert_eq!(messages.iter().map(|m| m.id).collect::<Vec<u64>>(), [1, 2]);
+    assert_eq!(messages[0].delivery_attempts, 2);
+    assert_eq!(messages[0].header(HEADER_LAST_FAILURE), Some("boom"));
+
+    assert!(channel.nack(1, Token(1), "").is_empty());
+    let message = channel.dequeue().unwrap();
+    assert_eq!(message.header(HEADER_LAST_FAILURE), Some("Rejected."));
+    // settling a message that is not in flight has no effect
+    assert!(channel.nack(1, Token(1), "boom").is_empty());
+    assert_eq!(channel.in_flight_num(), 1);
+}
+
+#[test]
+fn timed_out_messages_are_redelivered() {
+    let mut channel = channel();
+    channel.enqueue(message(GROUP_ID, TOPIC, 1));
+    assert_eq!(deliver(&channel, Token(1)), [1]);
+
+    assert!(channel.time_out(Instant::now()).is_empty());
+    assert_eq!(channel.in_flight_num(), 1);
+    assert!(
+        channel
+            .time_out(Instant::now() + Duration::from_secs(3600))
+            .is_empty()
+    );
+    assert_eq!(channel.in_flight_num(), 0);
+    let message = channel.dequeue().unwrap();
+    assert_eq!(message.id, 1);
+    assert_eq!(message.header(HEADER_LAST_FAILURE), Some("Ack timed out."));
+    // the ack of a timed out message comes too late
+    assert!(!channel.ack(1, Token(1)));
+}
+
+#[test]
+fn release_redelivers_the_messages_of_the_connection() {
+    let mut channel = channel();
+    channel.enqueue(message(GROUP_ID, TOPIC, 1));
+    assert_eq!(deliver(&channel, Token(1)), [1]);
+    channel.enqueue(message(GROUP_ID, TOPIC, 2));
+    assert_eq!(deliver(&channel, Token(2)), [2]);
+
+    assert!(channel.release(Token(1)).is_empty());
+    assert_eq!(channel.in_flight_num(), 1);
+    let message = channel.dequeue().unwrap();
+    assert_eq!(message.id, 1);
+    assert_eq!(
+        message.header(HEADER_LAST_FAILURE),
+        Some("Connection closed.")
+    );
+    assert!(channel.is_empty());
+    assert!(channel.ack(2, Token(2)));
+}
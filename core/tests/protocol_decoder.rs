@@ -0,0 +1,261 @@
+// fuzz-style corpus for the incremental protocol decoder.
+// every input is generated from a fixed seed, so a failure is reproducible.
+use std::io::{self, Read};
+
+use luminmq_core::{
+    compression::CompressionCodec,
+    msg::MessageDTO,
+    protocol::{
+        FrameLimits, Protocol, ProtocolDecoder, ProtocolEnd, ProtocolError, ProtocolHead, ReadState,
+    },
+    tool::codec::encode,
+};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+const SEED: u64 = 0x6c75_6d69_6e6d_71;
+
+fn message(data: Vec<u8>) -> MessageDTO {
+    MessageDTO::new(
+        "group-test".to_string(),
+        "topic-test".to_string(),
+        1,
+        1,
+        1,
+        data,
+    )
+}
+
+fn frame(data: Vec<u8>, codec: CompressionCodec) -> Vec<u8> {
+    let protocol = &mut Protocol::default();
+    protocol.insert_message(message(data));
+    let _ = protocol.ready();
+    protocol.head.compression = codec.code();
+    protocol.to_byte_vec()
+}
+
+// a frame with a valid head and checksum around arbitrary body bytes.
+fn raw_frame(body: &[u8]) -> Vec<u8> {
+    let mut head = ProtocolHead::default();
+    head.set_data_size(body.len() as u32);
+    let mut buf = head.to_byte_vec();
+    buf.extend_from_slice(body);
+    let checksum = crc32c::crc32c(&buf);
+    buf.extend_from_slice(&encode(ProtocolEnd::new(checksum)));
+    buf
+}
+
+fn corpus(rng: &mut StdRng) -> Vec<Vec<u8>> {
+    let mut frames = vec![
+        frame(Vec::new(), CompressionCodec::None),
+        frame(b"hello".to_vec(), CompressionCodec::None),
+        frame(vec![b'a'; 4096], CompressionCodec::Lz4),
+        frame(vec![b'z'; 4096], CompressionCodec::Zstd),
+    ];
+    for _ in 0..16 {
+        let len = rng.random_range(0..2048);
+        let data = (0..len).map(|_| rng.random()).collect();
+        frames.push(frame(data, CompressionCodec::None));
+    }
+    frames
+}
+
+// decode everything buffered, panics if the decoder does not make progress.
+fn drain(decoder: &mut ProtocolDecoder) -> (Vec<Protocol>, Vec<ProtocolError>) {
+    let mut frames = Vec::new();
+    let mut errors = Vec::new();
+    for _ in 0..100_000 {
+        match decoder.decode() {
+            Ok(Some(protocol)) => frames.push(protocol),
+            Ok(None) => return (frames, errors),
+            Err(e) => errors.push(e),
+        }
+    }
+    panic!("decoder did not make progress");
+}
+
+#[test]
+fn decodes_frames_split_at_every_position() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    for bytes in corpus(&mut rng).into_iter().take(6) {
+        for split in 0..=bytes.len() {
+            let mut decoder = ProtocolDecoder::new();
+            decoder.extend(&bytes[..split]);
+            let (mut frames, errors) = drain(&mut decoder);
+            decoder.extend(&bytes[split..]);
+            frames.extend(drain(&mut decoder).0);
+            assert!(errors.is_empty(), "split {}: {:?}", split, errors);
+            assert_eq!(frames.len(), 1, "split {}", split);
+            assert_eq!(decoder.buffered_len(), 0);
+        }
+    }
+}
+
+#[test]
+fn decodes_a_stream_fed_in_random_chunks() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let frames = corpus(&mut rng);
+    let stream: Vec<u8> = frames.concat();
+    let mut decoder = ProtocolDecoder::new();
+    let mut decoded = Vec::new();
+    let mut rest = &stream[..];
+    while !rest.is_empty() {
+        let n = rng.random_range(1..=rest.len().min(97));
+        decoder.extend(&rest[..n]);
+        rest = &rest[n..];
+        let (protocols, errors) = drain(&mut decoder);
+        assert!(errors.is_empty(), "{:?}", errors);
+        decoded.extend(protocols);
+    }
+    assert_eq!(decoded.len(), frames.len());
+}
+
+#[test]
+fn truncated_frames_wait_for_more_data() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    for bytes in corpus(&mut rng) {
+        for len in 0..bytes.len() {
+            let mut decoder = ProtocolDecoder::new();
+            decoder.extend(&bytes[..len]);
+            let (frames, errors) = drain(&mut decoder);
+            assert!(frames.is_empty() && errors.is_empty(), "length {}", len);
+        }
+    }
+}
+
+#[test]
+fn random_bytes_never_panic() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    for _ in 0..2000 {
+        let len = rng.random_range(0..512);
+        let bytes: Vec<u8> = (0..len).map(|_| rng.random()).collect();
+        let mut decoder = ProtocolDecoder::new();
+        decoder.extend(&bytes);
+        drain(&mut decoder);
+    }
+}
+
+#[test]
+fn mutated_frames_never_panic() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let frames = corpus(&mut rng);
+    for _ in 0..2000 {
+        let mut bytes = frames[rng.random_range(0..frames.len())].clone();
+        for _ in 0..rng.random_range(1..8) {
+            let i = rng.random_range(0..bytes.len());
+            match rng.random_range(0..3) {
+                0 => bytes[i] = rng.random(),
+                1 => bytes[i] ^= 1 << rng.random_range(0..8),
+                _ => bytes.truncate(i.max(1)),
+            }
+        }
+        let mut decoder = ProtocolDecoder::new();
+        decoder.extend(&bytes);
+        drain(&mut decoder);
+    }
+}
+
+#[test]
+fn skips_garbage_and_corrupted_frames() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let valid = frame(b"after".to_vec(), CompressionCodec::None);
+    for _ in 0..500 {
+        let mut bytes: Vec<u8> = (0..rng.random_range(1..256))
+            .map(|_| rng.random_range(b'A'..=b'Z'))
+            .collect();
+        // a frame with a broken checksum, its boundary is known so only the frame is lost
+        let mut corrupted = frame(b"corrupted".to_vec(), CompressionCodec::None);
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        bytes.extend_from_slice(&corrupted);
+        bytes.extend_from_slice(&valid);
+        let mut decoder = ProtocolDecoder::new();
+        decoder.extend(&bytes);
+        let (frames, errors) = drain(&mut decoder);
+        assert!(!errors.is_empty());
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].get_message().unwrap().data, b"after");
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+}
+
+#[test]
+fn rejects_oversized_frame_before_buffering_the_body() {
+    let limits = FrameLimits::new(1024, 4096);
+    let mut head = ProtocolHead::default();
+    head.set_data_size(u32::MAX);
+    let mut decoder = ProtocolDecoder::with_limits(limits);
+    decoder.extend(&head.to_byte_vec());
+    match decoder.decode() {
+        Err(e @ ProtocolError::FrameTooLarge { .. }) => assert!(e.is_fatal()),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(decoder.buffered_len(), 0);
+}
+
+#[test]
+fn rejects_frame_exceeding_read_buffer() {
+    let bytes = frame(vec![b'a'; 2048], CompressionCodec::None);
+    let mut decoder = ProtocolDecoder::with_limits(FrameLimits::new(4096, 1024));
+    let mut reader = &bytes[..];
+    assert_eq!(decoder.read_from(&mut reader).unwrap(), ReadState::Full);
+    assert_eq!(decoder.decode(), Ok(None));
+    assert_eq!(decoder.read_from(&mut reader).unwrap(), ReadState::Full);
+    match decoder.decode() {
+        Err(e @ ProtocolError::BufferOverflow { .. }) => assert!(e.is_fatal()),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn rejects_decompression_bomb() {
+    let bytes = frame(vec![0; 64 * 1024], CompressionCodec::Zstd);
+    assert!(bytes.len() < 1024);
+    let mut decoder = ProtocolDecoder::with_limits(FrameLimits::new(1024, 4096));
+    decoder.extend(&bytes);
+    match decoder.decode() {
+        Err(e @ ProtocolError::InvalidBody(_)) => assert!(!e.is_fatal()),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn forged_length_prefix_does_not_allocate() {
+    // a message body whose first string announces u64::MAX bytes
+    let mut body = vec![0u8];
+    body.push(253);
+    body.extend_from_slice(&u64::MAX.to_le_bytes());
+    let mut decoder = ProtocolDecoder::new();
+    decoder.extend(&raw_frame(&body));
+    match decoder.decode() {
+        Err(ProtocolError::InvalidBody(_)) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    // a 4 GB string, rejected by the decode limit instead of being allocated
+    let mut body = vec![0u8];
+    body.push(252);
+    body.extend_from_slice(&(u32::MAX - 1).to_le_bytes());
+    decoder.extend(&raw_frame(&body));
+    match decoder.decode() {
+        Err(ProtocolError::InvalidBody(_)) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+// a reader that never runs out of bytes.
+struct Flood;
+
+impl Read for Flood {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+}
+
+#[test]
+fn read_buffer_is_capped() {
+    let mut decoder = ProtocolDecoder::with_limits(FrameLimits::new(1024, 8192));
+    assert_eq!(decoder.read_from(Flood).unwrap(), ReadState::Full);
+    assert_eq!(decoder.buffered_len(), 8192);
+    drain(&mut decoder);
+    assert_eq!(decoder.buffered_len(), 0);
+}
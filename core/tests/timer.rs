@@ -0,0 +1,89 @@
+// the timing wheel of delayed messages, driven by explicit times.
+use std::time::Duration;
+
+use luminmq_core::{
+    msg::{ConsumerType, Message, MessageStatus, MessageType},
+    timer::{TICK_MILLIS, TimingWheel},
+    tool::time::now_millis,
+};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+const START: u64 = 1_760_000_000_123;
+
+#[test]
+fn expires_entries_in_deadline_order() {
+    let mut wheel = TimingWheel::new(START);
+    wheel.insert(START + 300, 3);
+    wheel.insert(START + 100, 1);
+    wheel.insert(START + 200, 2);
+    assert!(wheel.advance(START + 50).is_empty());
+    assert_eq!(wheel.advance(START + 250), vec![1, 2]);
+    assert_eq!(wheel.advance(START + 1000), vec![3]);
+    assert!(wheel.is_empty());
+}
+
+#[test]
+fn past_deadlines_expire_on_the_next_advance() {
+    let mut wheel = TimingWheel::new(START);
+    wheel.advance(START + 1000);
+    wheel.insert(START, "late");
+    assert_eq!(wheel.advance(START + 1000), vec!["late"]);
+}
+
+#[test]
+fn never_expires_early_or_loses_entries() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut wheel = TimingWheel::new(START);
+    let mut deadlines: Vec<u64> = (0..2000)
+        .map(|_| START + rng.random_range(0..10_000_000))
+        .collect();
+    for deadline in &deadlines {
+        wheel.insert(*deadline, *deadline);
+    }
+    assert_eq!(wheel.len(), deadlines.len());
+    let mut now = START;
+    let mut expired = Vec::new();
+    while !wheel.is_empty() {
+        now += rng.random_range(1..50_000);
+        for deadline in wheel.advance(now) {
+            assert!(deadline <= now, "{} expired at {}", deadline, now);
+            // at most one tick late
+            assert!(now - deadline < 50_000 + TICK_MILLIS);
+            expired.push(deadline);
+        }
+    }
+    deadlines.sort();
+    expired.sort();
+    assert_eq!(expired, deadlines);
+}
+
+#[test]
+fn holds_deadlines_beyond_the_top_level() {
+    let mut wheel = TimingWheel::new(START);
+    // thirty years, further than the top level reaches
+    let deadline = START + 30 * 365 * 24 * 3600 * 1000;
+    wheel.insert(deadline, ());
+    assert!(wheel.advance(deadline - 1000).is_empty());
+    assert_eq!(wheel.len(), 1);
+    // deadlines are rounded up to a whole tick
+    assert_eq!(wheel.advance(deadline + TICK_MILLIS).len(), 1);
+}
+
+#[test]
+fn delays_past_the_end_of_time_are_held() {
+    let mut message = Message::new(
+        "group-test".to_string(),
+        "topic-test".to_string(),
+        "later".to_string(),
+        MessageType::Business,
+        ConsumerType::Send,
+        MessageStatus::Success,
+    );
+    message.set_delay(Duration::MAX);
+    assert_eq!(message.deliver_at, u64::MAX);
+    assert!(message.is_scheduled(now_millis()));
+    let mut wheel = TimingWheel::new(START);
+    wheel.insert(message.deliver_at, ());
+    assert!(wheel.advance(now_millis()).is_empty());
+    assert_eq!(wheel.len(), 1);
+}
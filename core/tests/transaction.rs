@@ -0,0 +1,120 @@
+// messages staged in the transaction of a connection and committed to several channels at once.
+use mio::Token;
+
+use luminmq_core::{
+    channel::ChannelMode,
+    error::ErrorCode,
+    group::{GroupMode, Groups},
+    types::{ConnectionTransaction, MAX_TRANSACTION_SIZE},
+};
+
+use common::message_with_text as message;
+
+mod common;
+
+// a group with pull channels for the topics.
+fn group(group_id: &str, topics: &[&str]) {
+    Groups::default_insert_group(group_id.to_string(), GroupMode::Cluster);
+    for topic in topics {
+        Groups::insert_channel(group_id.to_string(), topic.to_string(), ChannelMode::Pull);
+    }
+}
+
+// (id, text) of the queued messages of a channel, oldest first.
+fn drain(group_id: &str, topic: &str) -> Vec<(u64, String)> {
+    let channel = Groups::get_channel(group_id.to_string(), topic.to_string()).unwrap();
+    let mut channel = channel.write().unwrap();
+    std::iter::from_fn(|| channel.dequeue())
+        .map(|message| (message.id, String::from_utf8(message.data).unwrap()))
+        .collect()
+}
+
+#[tokio::test]
+async fn staged_messages_are_committed_to_every_channel() {
+    group("group-commit", &["orders", "payments"]);
+    let token = Token(1);
+    assert!(ConnectionTransaction::begin(token));
+    assert!(!ConnectionTransaction::begin(token));
+    assert!(ConnectionTransaction::stage(
+        &token,
+        vec![
+            message("group-commit", "orders", "order-1"),
+            message("group-commit", "payments", "payment-1"),
+        ],
+    ));
+    assert!(ConnectionTransaction::stage(
+        &token,
+        vec![message("group-commit", "orders", "order-2")],
+    ));
+    // consumers see nothing before the commit
+    assert!(drain("group-commit", "orders").is_empty());
+    assert!(drain("group-commit", "payments").is_empty());
+
+    let ids = Groups::insert_transaction(ConnectionTransaction::remove(&token).unwrap()).unwrap();
+    assert!(!ConnectionTransaction::is_open(&token));
+    // the ids are returned in the order the messages were sent
+    assert_eq!(ids.len(), 3);
+    assert_eq!(
+        drain("group-commit", "orders"),
+        [
+            (ids[0], "order-1".to_string()),
+            (ids[2], "order-2".to_string())
+        ]
+    );
+    assert_eq!(
+        drain("group-commit", "payments"),
+        [(ids[1], "payment-1".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn a_missing_channel_rejects_the_whole_transaction() {
+    group("group-missing", &["orders"]);
+    let messages = vec![
+        message("group-missing", "orders", "order-1"),
+        message("group-missing", "refunds", "refund-1"),
+    ];
+    let error = Groups::insert_transaction(messages).unwrap_err();
+    assert_eq!(error.code, ErrorCode::UnknownTopic);
+    assert!(drain("group-missing", "orders").is_empty());
+}
+
+#[tokio::test]
+async fn a_transaction_stages_at_most_the_max_size() {
+    group("group-size", &["orders"]);
+    let token = Token(2);
+    // nothing is staged without an open transaction
+    assert!(!ConnectionTransaction::stage(
+        &token,
+        vec![message("group-size", "orders", "order")],
+    ));
+    assert!(ConnectionTransaction::begin(token));
+    let messages = vec![message("group-size", "orders", "order"); MAX_TRANSACTION_SIZE];
+    assert!(ConnectionTransaction::stage(&token, messages));
+    // a stage past the limit is rejected whole, the staged messages are kept
+    assert!(!ConnectionTransaction::stage(
+        &token,
+        vec![message("group-size", "orders", "order")],
+    ));
+    let staged = ConnectionTransaction::remove(&token).unwrap();
+    assert_eq!(staged.len(), MAX_TRANSACTION_SIZE);
+}
+
+#[tokio::test]
+async fn abort_discards_the_staged_messages() {
+    group("group-abort", &["orders"]);
+    let token = Token(3);
+    assert!(ConnectionTransaction::begin(token));
+    assert!(ConnectionTransaction::stage(
+        &token,
+        vec![message("group-abort", "orders", "order-1")],
+    ));
+    // an abort or a closed connection removes the transaction without committing it
+    assert_eq!(ConnectionTransaction::remove(&token).unwrap().len(), 1);
+    assert!(ConnectionTransaction::remove(&token).is_none());
+    assert!(!ConnectionTransaction::is_open(&token));
+    assert!(drain("group-abort", "orders").is_empty());
+    // the connection may open a new transaction
+    assert!(ConnectionTransaction::begin(token));
+    assert_eq!(ConnectionTransaction::remove(&token).unwrap().len(), 0);
+}
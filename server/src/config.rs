@@ -1,6 +1,7 @@
-use std::sync::Mutex;
+use std::{sync::Mutex, time::Duration};
 
 use lazy_static::lazy_static;
+use luminmq_core::compression::CompressionCodec;
 
 lazy_static! {
     // max event poll capacity
@@ -9,4 +10,22 @@ lazy_static! {
     pub static ref LISTENER_PORT: Mutex<String> = Mutex::new("0.0.0.0:8080".to_string());
     // http listener port
     pub static ref HTTP_LISTENER_PORT: Mutex<String> = Mutex::new("0.0.0.0:8081".to_string());
+    // codec used to compress frame bodies for clients that negotiated compression
+    pub static ref COMPRESSION_CODEC: Mutex<CompressionCodec> = Mutex::new(CompressionCodec::Lz4);
+    // frame bodies smaller than this number of bytes are sent uncompressed
+    pub static ref COMPRESSION_THRESHOLD: Mutex<usize> = Mutex::new(1024);
+    // zstd compression level
+    pub static ref COMPRESSION_LEVEL: Mutex<i32> = Mutex::new(3);
+    // largest accepted frame body in bytes, larger frames disconnect the client
+    pub static ref MAX_FRAME_SIZE: Mutex<usize> = Mutex::new(16 * 1024 * 1024);
+    // largest number of undecoded bytes buffered for a connection
+    pub static ref MAX_READ_BUFFER_SIZE: Mutex<usize> = Mutex::new(32 * 1024 * 1024);
+    // node id of this broker in message ids, brokers sharing consumers must use different node ids
+    pub static ref NODE_ID: Mutex<u16> = Mutex::new(0);
+    // pushed messages not acknowledged within this time are redelivered
+    pub static ref ACK_TIMEOUT: Mutex<Duration> = Mutex::new(Duration::from_secs(30));
+    // idle connections are pinged after this interval
+    pub static ref HEARTBEAT_INTERVAL: Mutex<Duration> = Mutex::new(Duration::from_secs(10));
+    // connections that sent nothing for this long are considered dead and closed
+    pub static ref HEARTBEAT_TIMEOUT: Mutex<Duration> = Mutex::new(Duration::from_secs(30));
 }
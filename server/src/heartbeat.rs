@@ -0,0 +1,96 @@
+/// liveness of the connections, decides which ones the heartbeat pings or closes.
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use mio::Token;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeartbeatAction {
+    Keep,
+    Ping,
+    Close,
+}
+
+// the action of the heartbeat for a connection accepted `since_accept` ago and idle for `idle`.
+// a connection must complete the handshake within one interval, whatever it sends before.
+// an established connection is pinged once idle for an interval and closed after the timeout.
+pub fn heartbeat_action(
+    since_accept: Duration,
+    idle: Duration,
+    established: bool,
+    interval: Duration,
+    timeout: Duration,
+) -> HeartbeatAction {
+    if idle >= timeout || (!established && since_accept >= interval) {
+        HeartbeatAction::Close
+    } else if established && idle >= interval {
+        HeartbeatAction::Ping
+    } else {
+        HeartbeatAction::Keep
+    }
+}
+
+struct Liveness {
+    accepted: Instant,
+    // the last time the connection was readable
+    seen: Instant,
+}
+
+pub struct Heartbeats {
+    interval: Duration,
+    timeout: Duration,
+    // k: token v: liveness of the connection
+    connections: HashMap<Token, Liveness>,
+}
+
+impl Heartbeats {
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Self {
+            interval,
+            timeout,
+            connections: HashMap::new(),
+        }
+    }
+    // a connection was accepted.
+    pub fn accept(&mut self, token: Token, now: Instant) {
+        self.connections.insert(
+            token,
+            Liveness {
+                accepted: now,
+                seen: now,
+            },
+        );
+    }
+    // a connection sent something, unknown connections are ignored.
+    pub fn seen(&mut self, token: Token, now: Instant) {
+        if let Some(liveness) = self.connections.get_mut(&token) {
+            liveness.seen = now;
+        }
+    }
+    pub fn remove(&mut self, token: &Token) {
+        self.connections.remove(token);
+    }
+    // the connections to ping or close, `established` tells which completed the handshake.
+    pub fn check(
+        &self,
+        now: Instant,
+        established: impl Fn(&Token) -> bool,
+    ) -> Vec<(Token, HeartbeatAction)> {
+        self.connections
+            .iter()
+            .map(|(token, liveness)| {
+                let action = heartbeat_action(
+                    now.saturating_duration_since(liveness.accepted),
+                    now.saturating_duration_since(liveness.seen),
+                    established(token),
+                    self.interval,
+                    self.timeout,
+                );
+                (*token, action)
+            })
+            .filter(|(_, action)| *action != HeartbeatAction::Keep)
+            .collect()
+    }
+}
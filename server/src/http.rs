@@ -5,6 +5,7 @@ use axum::{
     response::IntoResponse,
     routing::{get, post},
 };
+use luminmq_core::group::Groups;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
@@ -53,7 +54,8 @@ impl LuminMQHtppService {
             .route(
                 "/topic/getMessageNum",
                 post(move |body| Self::topic_get_message_num(body)),
-            );
+            )
+            .route("/topic/getExpiredNum", post(Self::topic_get_expired_num));
         let addr = HTTP_LISTENER_PORT.lock().unwrap().clone();
         let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
         axum::serve(listener, app).await.unwrap();
@@ -136,6 +138,15 @@ impl LuminMQHtppService {
             Json(json!({ "message": "successfully"})),
         )
     }
+    // number of messages of a topic that expired before they were consumed.
+    async fn topic_get_expired_num(Json(vo): Json<ChannelVO>) -> impl IntoResponse {
+        let expired_num = Groups::channel_expired_num(vo.group_id, vo.topic);
+        (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            Json(json!({ "message": "successfully", "expired_num": expired_num })),
+        )
+    }
 }
 
 // create order book view object
@@ -162,3 +173,12 @@ struct TopicVO {
 }
 
 impl TopicVO {}
+
+// channel view object, a topic of a group
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ChannelVO {
+    pub group_id: String,
+    pub topic: String,
+}
+
+impl ChannelVO {}
@@ -1,3 +1,4 @@
 pub mod config;
+pub mod heartbeat;
 pub mod http;
 pub mod server;
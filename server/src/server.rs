@@ -1,11 +1,20 @@
 /// a server module for lumin message queues.
-use std::io::{self};
+use std::{
+    collections::HashMap,
+    io::{self},
+    time::Instant,
+};
 
 use luminmq_core::{
+    channel::DeliveryOptions,
+    compression::CompressionOptions,
+    connection::Connection,
     group::Groups,
-    msg,
-    protocol::Protocol,
-    types::{ConnectionPool, ConnectionPoolAndGroupBind},
+    id::MessageId,
+    msg::{Message, MessageStatus, SystemAction},
+    protocol::{FrameLimits, Protocol, ProtocolDecoder, ProtocolError},
+    timer::Scheduler,
+    types::{ConnectionPool, ConnectionPoolAndGroupBind, ConnectionSession, ConnectionTransaction},
 };
 use mio::{
     Events, Interest, Poll, Registry, Token,
@@ -16,7 +25,14 @@ use tokio::stream;
 use tracing::{Level, event, info};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::config::LISTENER_PORT;
+use crate::{
+    config::{
+        ACK_TIMEOUT, COMPRESSION_CODEC, COMPRESSION_LEVEL, COMPRESSION_THRESHOLD,
+        HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT, LISTENER_PORT, MAX_FRAME_SIZE, MAX_READ_BUFFER_SIZE,
+        NODE_ID,
+    },
+    heartbeat::{HeartbeatAction, Heartbeats},
+};
 
 const SERVER_TOKEN: Token = Token(0);
 
@@ -25,17 +41,36 @@ pub struct LuminMQServer;
 impl LuminMQServer {
     pub async fn start() -> std::io::Result<()> {
         let addr = LISTENER_PORT.lock().unwrap().parse().unwrap();
+        CompressionOptions::set(CompressionOptions::new(
+            *COMPRESSION_CODEC.lock().unwrap(),
+            *COMPRESSION_THRESHOLD.lock().unwrap(),
+            *COMPRESSION_LEVEL.lock().unwrap(),
+        ));
+        MessageId::set_node_id(*NODE_ID.lock().unwrap());
+        DeliveryOptions::set(DeliveryOptions::new(*ACK_TIMEOUT.lock().unwrap()));
+        FrameLimits::set(FrameLimits::new(
+            *MAX_FRAME_SIZE.lock().unwrap(),
+            *MAX_READ_BUFFER_SIZE.lock().unwrap(),
+        ));
+        // delayed messages are enqueued into their channels when due
+        Scheduler::start();
         let mut listener = TcpListener::bind(addr)?;
         let mut poll = Poll::new()?;
         let mut events = Events::with_capacity(1024);
-        // let mut connections = HashMap::<Token, TcpStream>::new();
+        // per-connection decoders, keep partially received frames between events.
+        let mut decoders = HashMap::<Token, ProtocolDecoder>::new();
+        // when each connection was accepted and last readable, used to detect dead peers.
+        let heartbeat_interval = *HEARTBEAT_INTERVAL.lock().unwrap();
+        let mut heartbeats =
+            Heartbeats::new(heartbeat_interval, *HEARTBEAT_TIMEOUT.lock().unwrap());
+        let mut last_heartbeat = Instant::now();
         poll.registry()
             .register(&mut listener, Token(0), Interest::READABLE)
             .unwrap();
         let mut unique_token = Token(SERVER_TOKEN.0 + 1);
         event!(Level::INFO, "STARTED SUCCESS");
         loop {
-            poll.poll(&mut events, None).unwrap();
+            poll.poll(&mut events, Some(heartbeat_interval)).unwrap();
             for event in &events {
                 match event.token() {
                     SERVER_TOKEN => loop {
@@ -55,42 +90,119 @@ impl LuminMQServer {
                             Interest::READABLE.add(Interest::WRITABLE),
                         )?;
                         ConnectionPool::insert(token, connection);
-                        ConnectionPoolAndGroupBind::insert(
-                            token,
-                            ("group-test".to_string(), "topic-test".to_string()),
-                        );
+                        decoders.insert(token, ProtocolDecoder::new());
+                        heartbeats.accept(token, Instant::now());
                     },
                     // system buffer changes
                     token => {
-                        ConnectionPool::handle(&token, |stream| {
+                        if event.is_readable() {
+                            heartbeats.seen(token, Instant::now());
+                        }
+                        let decoder = decoders.entry(token).or_default();
+                        let flag = ConnectionPool::handle(&token, |connection| {
                             event!(
                                 Level::INFO,
                                 "client access received, address:{:?}",
-                                stream.peer_addr()
+                                connection.stream().peer_addr()
                             );
-                            let flag = match handle_connection_event(stream, event) {
+                            match handle_connection_event(token, connection, decoder, event) {
                                 Ok(_) => false,
                                 Err(_) => true,
-                            };
-                            if flag {
-                                ConnectionPool::remove(token);
                             }
                         });
+                        if flag.unwrap_or(false) {
+                            close(token, &mut decoders, &mut heartbeats);
+                        }
                     }
                     _ => {}
                 }
             }
+            if last_heartbeat.elapsed() >= heartbeat_interval {
+                last_heartbeat = Instant::now();
+                heartbeat(&mut decoders, &mut heartbeats);
+            }
         }
     }
 }
 
-fn handle_connection_event(connection: &mut TcpStream, event: &Event) -> io::Result<bool> {
+fn handle_connection_event(
+    token: Token,
+    connection: &Connection,
+    decoder: &mut ProtocolDecoder,
+    event: &Event,
+) -> io::Result<bool> {
+    if event.is_writable() {
+        // frames the socket did not accept before are written now
+        connection.flush()?;
+    }
     if event.is_readable() {
-        Protocol::handle(&connection, |msg| {});
+        let result = Protocol::handle(connection.stream(), decoder, |protocol| {
+            if protocol.is_batch() {
+                Message::handle_batch(protocol.get_messages(), token, connection)
+            } else {
+                match protocol.get_message() {
+                    Ok(mut msg) => msg.handle(token, connection),
+                    Err(_) => Ok(()),
+                }
+            }
+        });
+        if let Err(e) = result {
+            // a fatal protocol violation is answered with an error frame before disconnecting
+            if let Some(violation) = e.get_ref().and_then(|e| e.downcast_ref::<ProtocolError>()) {
+                event!(Level::WARN, "protocol violation: {}", violation);
+                let _ = violation.to_error().writer(connection, 0);
+            }
+            return Err(e);
+        }
     }
     Ok(false)
 }
 
+// ping idle connections, close the ones that sent nothing within the heartbeat timeout and the
+// ones that did not complete the handshake within one interval.
+fn heartbeat(decoders: &mut HashMap<Token, ProtocolDecoder>, heartbeats: &mut Heartbeats) {
+    let actions = heartbeats.check(Instant::now(), |token| {
+        ConnectionSession::get(token).is_some()
+    });
+    for (token, action) in actions {
+        match action {
+            HeartbeatAction::Ping => {
+                let ping = Message::system(SystemAction::Ping, MessageStatus::None, []);
+                ConnectionPool::handle(&token, |connection| {
+                    let _ =
+                        ping.session_writer(connection, ConnectionSession::get(&token).as_ref());
+                });
+            }
+            HeartbeatAction::Close => {
+                event!(
+                    Level::WARN,
+                    "heartbeat timeout, closing connection {:?}",
+                    token
+                );
+                close(token, decoders, heartbeats);
+            }
+            HeartbeatAction::Keep => {}
+        }
+    }
+}
+
+// forget a closed connection, pushes stop targeting it
+// and the messages in flight to it are redelivered.
+fn close(
+    token: Token,
+    decoders: &mut HashMap<Token, ProtocolDecoder>,
+    heartbeats: &mut Heartbeats,
+) {
+    ConnectionPool::remove(token);
+    ConnectionSession::remove(&token);
+    ConnectionPoolAndGroupBind::remove(&token);
+    // an open transaction is discarded
+    ConnectionTransaction::remove(&token);
+    Groups::release(token);
+    decoders.remove(&token);
+    heartbeats.remove(&token);
+}
+
 fn next(current: &mut Token) -> Token {
     let next = current.0;
     current.0 += 1;
@@ -0,0 +1,87 @@
+// the heartbeat decisions of the connections, driven by explicit times.
+use std::time::{Duration, Instant};
+
+use mio::Token;
+
+use luminmq_server::heartbeat::{HeartbeatAction, Heartbeats, heartbeat_action};
+
+const INTERVAL: Duration = Duration::from_secs(10);
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[test]
+fn established_connections_are_pinged_then_closed() {
+    let action = |idle| heartbeat_action(secs(100), secs(idle), true, INTERVAL, TIMEOUT);
+    assert_eq!(action(0), HeartbeatAction::Keep);
+    assert_eq!(action(9), HeartbeatAction::Keep);
+    assert_eq!(action(10), HeartbeatAction::Ping);
+    assert_eq!(action(29), HeartbeatAction::Ping);
+    assert_eq!(action(30), HeartbeatAction::Close);
+}
+
+#[test]
+fn connections_without_a_handshake_are_closed_after_one_interval() {
+    let action = |since_accept, idle| {
+        heartbeat_action(secs(since_accept), secs(idle), false, INTERVAL, TIMEOUT)
+    };
+    assert_eq!(action(0, 0), HeartbeatAction::Keep);
+    assert_eq!(action(9, 9), HeartbeatAction::Keep);
+    // sending frames does not keep a connection without a handshake alive
+    assert_eq!(action(10, 0), HeartbeatAction::Close);
+}
+
+// the actions of the heartbeat ordered by token.
+fn check(
+    heartbeats: &Heartbeats,
+    now: Instant,
+    established: impl Fn(&Token) -> bool,
+) -> Vec<(Token, HeartbeatAction)> {
+    let mut actions = heartbeats.check(now, established);
+    actions.sort_by_key(|(token, _)| *token);
+    actions
+}
+
+#[test]
+fn reads_keep_established_connections_alive() {
+    let start = Instant::now();
+    let mut heartbeats = Heartbeats::new(INTERVAL, TIMEOUT);
+    heartbeats.accept(Token(1), start);
+    heartbeats.accept(Token(2), start);
+    assert!(check(&heartbeats, start + secs(5), |_| true).is_empty());
+
+    heartbeats.seen(Token(1), start + secs(25));
+    assert_eq!(
+        check(&heartbeats, start + secs(30), |_| true),
+        [(Token(2), HeartbeatAction::Close)]
+    );
+    heartbeats.remove(&Token(2));
+    assert_eq!(
+        check(&heartbeats, start + secs(35), |_| true),
+        [(Token(1), HeartbeatAction::Ping)]
+    );
+    // unknown connections are not tracked
+    heartbeats.seen(Token(3), start + secs(35));
+    assert_eq!(check(&heartbeats, start + secs(60), |_| true).len(), 1);
+}
+
+#[test]
+fn only_established_connections_outlive_the_first_interval() {
+    let start = Instant::now();
+    let mut heartbeats = Heartbeats::new(INTERVAL, TIMEOUT);
+    heartbeats.accept(Token(1), start);
+    heartbeats.accept(Token(2), start + secs(5));
+    // the peer without a handshake keeps sending, e.g. pongs
+    heartbeats.seen(Token(2), start + secs(14));
+    let established = |token: &Token| *token == Token(1);
+    assert!(check(&heartbeats, start + secs(9), established).is_empty());
+    assert_eq!(
+        check(&heartbeats, start + secs(15), established),
+        [
+            (Token(1), HeartbeatAction::Ping),
+            (Token(2), HeartbeatAction::Close)
+        ]
+    );
+}